@@ -10,6 +10,7 @@
 ///  Exit error codes:
 ///  * 1: invalid path to input file
 ///  * 2: can not open input file for reading
+///  * 7: an input file is a directory
 /*
 * References:
 * - Writing CLI applications
@@ -25,11 +26,13 @@
 *
 */
 use clap::{App, Arg};
+use regex::Regex;
 
 use std::env;
 use std::ffi::OsString;
 use std::fs::File;
 use std::io::{self, BufRead, Write};
+use std::path::Path;
 use std::process;
 
 extern crate toolslib;
@@ -37,12 +40,37 @@ use crate::toolslib::{get_file_paths, ErrCode};
 
 const VERSION: &str = "ver. 0.0.2";
 
+/// Distance between the tab stops the numbered lines are aligned to with -T
+const TAB_WIDTH: usize = 8;
+
 /// A structure that defines how the output is formatted.
 struct OutputFormatter {
     has_line_numbers: bool,
+    number_width: usize,
     only_non_blank: bool,
+    /// Only the lines matching the expression are numbered
+    number_matching: Option<Regex>,
     squeze_blank: bool,
+    blank_is_whitespace: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
     ignore_errors: bool,
+    chop: Option<usize>,
+    /// The lines matching the expression are not written
+    grep_out: Option<Regex>,
+    file_headers: bool,
+    file_footers: bool,
+    file_separator: Option<String>,
+    ensure_final_newline: bool,
+    reverse: bool,
+    /// Number of lines omitted at the start of each input
+    skip: usize,
+    /// The omitted lines advance the line counter
+    number_skipped: bool,
+    /// The lines of the inputs are written in turns
+    interleave: bool,
+    stats: bool,
     inputs: Vec<String>,
 }
 
@@ -51,12 +79,43 @@ impl OutputFormatter {
     fn new() -> OutputFormatter {
         OutputFormatter {
             has_line_numbers: false,
+            number_width: 5,
             only_non_blank: false,
+            number_matching: None,
             squeze_blank: false,
+            blank_is_whitespace: false,
+            show_ends: false,
+            show_tabs: false,
+            show_nonprinting: false,
             ignore_errors: false,
+            chop: None,
+            grep_out: None,
+            file_headers: false,
+            file_footers: false,
+            file_separator: None,
+            ensure_final_newline: false,
+            reverse: false,
+            skip: 0,
+            number_skipped: false,
+            interleave: false,
+            stats: false,
             inputs: Vec::new(),
         }
     }
+
+    /// Returns true if the contents have to be processed line by line, when
+    /// false they are copied byte for byte.
+    fn is_line_oriented(&self) -> bool {
+        self.has_line_numbers
+            || self.squeze_blank
+            || self.only_non_blank
+            || self.show_ends
+            || self.show_tabs
+            || self.show_nonprinting
+            || self.reverse
+            || self.chop.is_some()
+            || self.grep_out.is_some()
+    }
 }
 
 /// Read the command line arguments and parse them into the OutputFormatter
@@ -85,6 +144,29 @@ where
                 .takes_value(false)
                 .help("number only non blank lines"),
         )
+        .arg(
+            Arg::with_name("number-matching")
+                .long("number-matching")
+                .takes_value(true)
+                .value_name("REGEX")
+                .conflicts_with_all(&["number", "number-nonblank"])
+                .validator(|v| match Regex::new(&v) {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("REGEX must be a valid regular expression")),
+                })
+                .help("number only the lines matching REGEX"),
+        )
+        .arg(
+            Arg::with_name("number-width")
+                .long("number-width")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("N must be a positive integer")),
+                })
+                .help("width of the line number column (default 5)"),
+        )
         .arg(
             Arg::with_name("squeze-blank")
                 .short("s")
@@ -92,6 +174,41 @@ where
                 .takes_value(false)
                 .help("suppress repeated blank lines"),
         )
+        .arg(
+            Arg::with_name("blank-is-whitespace")
+                .long("blank-is-whitespace")
+                .takes_value(false)
+                .requires("squeze-blank")
+                .help("squeeze lines with only whitespace too, like spaces, tabs and form feeds"),
+        )
+        .arg(
+            Arg::with_name("show-all")
+                .short("A")
+                .long("show-all")
+                .takes_value(false)
+                .help("equivalent to -vET"),
+        )
+        .arg(
+            Arg::with_name("show-ends")
+                .short("E")
+                .long("show-ends")
+                .takes_value(false)
+                .help("display $ at end of each line"),
+        )
+        .arg(
+            Arg::with_name("show-tabs")
+                .short("T")
+                .long("show-tabs")
+                .takes_value(false)
+                .help("display TAB characters as ^I"),
+        )
+        .arg(
+            Arg::with_name("show-nonprinting")
+                .short("v")
+                .long("show-nonprinting")
+                .takes_value(false)
+                .help("use ^ and M- notation, except for LFD and TAB"),
+        )
         .arg(
             Arg::with_name("ignore-errors")
                 .short("i")
@@ -99,12 +216,103 @@ where
                 .takes_value(false)
                 .help("Ignore errors that affect invidiual files"),
         )
+        .arg(
+            Arg::with_name("chop")
+                .long("chop")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("N must be a positive integer")),
+                })
+                .help("truncate output lines to N columns"),
+        )
+        .arg(
+            Arg::with_name("grep-out")
+                .long("grep-out")
+                .takes_value(true)
+                .value_name("REGEX")
+                .validator(|v| match Regex::new(&v) {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("REGEX must be a valid regular expression")),
+                })
+                .help("do not write the lines matching REGEX, they are not numbered"),
+        )
+        .arg(
+            Arg::with_name("file-headers")
+                .long("file-headers")
+                .takes_value(false)
+                .help("print a `==> name <==` header before each file"),
+        )
+        .arg(
+            Arg::with_name("file-footers")
+                .long("file-footers")
+                .takes_value(false)
+                .help("print a `<== name ==>` footer after each file"),
+        )
+        .arg(
+            Arg::with_name("file-separator")
+                .long("file-separator")
+                .takes_value(true)
+                .value_name("STR")
+                .help("print a line with STR between the contents of consecutive files"),
+        )
+        .arg(
+            Arg::with_name("ensure-final-newline")
+                .long("ensure-final-newline")
+                .takes_value(false)
+                .help("end the output with a newline if the last input does not"),
+        )
+        .arg(
+            Arg::with_name("reverse")
+                .short("r")
+                .long("reverse")
+                .takes_value(false)
+                .help("write the lines of each file in reverse order, like tac"),
+        )
+        .arg(
+            Arg::with_name("skip")
+                .long("skip")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("N must be a non negative integer")),
+                })
+                .help("omit the first N lines of each file, the numbering starts after them"),
+        )
+        .arg(
+            Arg::with_name("number-skipped")
+                .long("number-skipped")
+                .takes_value(false)
+                .requires("skip")
+                .help("count the lines omitted by --skip, the numbers are the line numbers in the file"),
+        )
+        .arg(
+            Arg::with_name("interleave")
+                .long("interleave")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "reverse",
+                    "skip",
+                    "file-headers",
+                    "file-footers",
+                    "file-separator",
+                ])
+                .help("write a line of every file in turns, until all the files end"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .takes_value(false)
+                .help("write the number of lines, bytes and files to stderr"),
+        )
         .arg(
             Arg::with_name("inputs")
-                .help("Input files")
-                .required(true)
+                .help("Input files, `-` or none reads the standard input")
                 .takes_value(true)
-                .multiple(true),
+                .multiple(true)
+                .default_value("-"),
         )
         .get_matches_from(itr);
 
@@ -121,12 +329,91 @@ where
         output_formatter.squeze_blank = true;
     }
 
+    if matches.is_present("blank-is-whitespace") {
+        output_formatter.blank_is_whitespace = true;
+    }
+
+    if matches.is_present("show-all") {
+        output_formatter.show_nonprinting = true;
+        output_formatter.show_ends = true;
+        output_formatter.show_tabs = true;
+    }
+
+    if matches.is_present("show-ends") {
+        output_formatter.show_ends = true;
+    }
+
+    if matches.is_present("show-tabs") {
+        output_formatter.show_tabs = true;
+    }
+
+    if matches.is_present("show-nonprinting") {
+        output_formatter.show_nonprinting = true;
+    }
+
     if matches.is_present("ignore-errors") {
         output_formatter.ignore_errors = true;
     }
 
-    // This is only safe because the argument is required.
+    if matches.is_present("file-headers") {
+        output_formatter.file_headers = true;
+    }
+
+    if matches.is_present("file-footers") {
+        output_formatter.file_footers = true;
+    }
+
+    if matches.is_present("reverse") {
+        output_formatter.reverse = true;
+    }
+
+    if matches.is_present("stats") {
+        output_formatter.stats = true;
+    }
+
+    if matches.is_present("number-skipped") {
+        output_formatter.number_skipped = true;
+    }
+
+    if matches.is_present("interleave") {
+        output_formatter.interleave = true;
+    }
+
+    if matches.is_present("ensure-final-newline") {
+        output_formatter.ensure_final_newline = true;
+    }
+
+    if let Some(separator) = matches.value_of("file-separator") {
+        output_formatter.file_separator = Some(String::from(separator));
+    }
+
+    // The validator guarantees that the expression is valid
+    if let Some(pattern) = matches.value_of("number-matching") {
+        output_formatter.has_line_numbers = true;
+        output_formatter.number_matching = Regex::new(pattern).ok();
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(n) = matches.value_of("number-width") {
+        output_formatter.number_width = n.parse::<usize>().unwrap();
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(n) = matches.value_of("skip") {
+        output_formatter.skip = n.parse::<usize>().unwrap();
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(n) = matches.value_of("chop") {
+        output_formatter.chop = n.parse::<usize>().ok();
+    }
+
+    // The validator guarantees that the expression is valid
+    if let Some(pattern) = matches.value_of("grep-out") {
+        output_formatter.grep_out = Regex::new(pattern).ok();
+    }
 
+    // This is only safe because the argument has a default value.
     let tmp: Vec<_> = matches.values_of("inputs").unwrap().collect();
     for file_name in tmp {
         output_formatter.inputs.push(file_name.to_string());
@@ -144,154 +431,1525 @@ where
 /// * `output_formatter` - OutputFormatter structure containing the formatting parameters
 ///
 /// Appends a number to the line if the -n switch was passed in the command line arguments.
-/// Ignores blank lines if -b switch was passsed in the command line arguments.
+/// Ignores blank lines if -b switch was passsed in the command line arguments,
+/// and the lines that do not match the --number-matching expression. The
+/// lines without a number are aligned with the numbered ones.
+/// Appends a `$` to the line if the -E switch was passed in the command line
+/// arguments. The contents of the line are never modified, trailing
+/// whitespace included.
+/// Replaces the tabs in the line with `^I` if the -T switch was passed in the
+/// command line arguments.
 ///
+/// With -T the number is padded to the next tab stop, so the contents of all
+/// the lines start in the same column whatever the number of digits.
 fn format_output_line(
     line: &String,
     line_number: u32,
     output_formatter: &OutputFormatter,
 ) -> String {
-    let is_blank = line == "";
-    let formated_line = format!(
-        "{}{}",
-        if is_blank & output_formatter.only_non_blank {
-            format!("{:<5}:", String::from(""))
-        } else if output_formatter.has_line_numbers {
-            format!("{:<5}: ", line_number)
-        } else {
-            String::from("")
-        },
+    let is_numbered = is_numbered(line, output_formatter);
+    let tabs_shown;
+    let line = if output_formatter.show_tabs {
+        tabs_shown = line.replace('\t', "^I");
+        &tabs_shown
+    } else {
         line
-    );
-    String::from(formated_line.trim_end())
+    };
+    let is_blank = line.is_empty();
+    let width = output_formatter.number_width;
+    let prefix = if !output_formatter.has_line_numbers {
+        String::from("")
+    } else if is_numbered {
+        format!("{:<width$}: ", line_number, width = width)
+    } else {
+        format!("{:<width$}: ", String::from(""), width = width)
+    };
+    let prefix = if output_formatter.show_tabs && !prefix.is_empty() {
+        let stop = prefix.len().div_ceil(TAB_WIDTH) * TAB_WIDTH;
+        format!("{:<stop$}", prefix, stop = stop)
+    } else {
+        prefix
+    };
+    // The numbers of blank lines are not followed by a space
+    let prefix = if is_blank { prefix.trim_end() } else { &prefix };
+    format!(
+        "{}{}{}",
+        prefix,
+        line,
+        if output_formatter.show_ends { "$" } else { "" }
+    )
 }
 
-fn main() {
-    let output_formatter = read_arguments(env::args_os());
+/// Returns true if the line gets a number: with -b the lines that are not
+/// empty, with --number-matching the lines matching the expression, all the
+/// lines otherwise.
+fn is_numbered(line: &str, output_formatter: &OutputFormatter) -> bool {
+    if output_formatter.only_non_blank {
+        !line.is_empty()
+    } else if let Some(re) = &output_formatter.number_matching {
+        re.is_match(line)
+    } else {
+        true
+    }
+}
 
-    let file_paths = match get_file_paths(&output_formatter.inputs, output_formatter.ignore_errors)
-    {
-        Ok(file_paths) => file_paths,
-        Err(rc) => {
-            process::exit(rc as i32);
-        }
-    };
+/// Returns true if the line is blank for squeezing.
+///
+/// # Arguments
+///
+/// * `line` - String to be checked
+/// * `whitespace` - bool, when set lines with only whitespace are blank too
+///
+/// By default only empty lines are blank, like in GNU cat.
+fn is_squeezable(line: &str, whitespace: bool) -> bool {
+    if whitespace {
+        line.trim().is_empty()
+    } else {
+        line.is_empty()
+    }
+}
 
-    // For every file read the contents
-    let mut next_line_number = 0u32;
-    let stdout = io::stdout();
-    let mut handle = io::BufWriter::new(stdout);
+/// Returns the representation of a byte in the notation of `cat -v`.
+///
+/// # Arguments
+///
+/// * `byte` - u8 the byte to render
+///
+/// Control characters are rendered in caret notation, like `^A`, DEL is `^?`
+/// and bytes with the high bit set are `M-` followed by the rendering of the
+/// low 7 bits. Tabs and newlines are left unchanged, they are handled by -T
+/// and -E.
+fn render_nonprinting(byte: u8) -> String {
+    match byte {
+        b'\t' | b'\n' => (byte as char).to_string(),
+        0x80..=0xff => format!("M-{}", render_caret(byte - 0x80)),
+        _ => render_caret(byte),
+    }
+}
 
-    for file_path in &file_paths {
-        let lines = match File::open(&file_path) {
-            Err(err_code) => {
-                eprintln!(
-                    "ERROR opening file `{}` for reading: {}",
-                    file_path.display(),
-                    err_code
-                );
-                if output_formatter.ignore_errors {
-                    continue;
-                } else {
-                    process::exit(ErrCode::ErrorCannotOpenFileForReading as i32);
-                }
+/// Returns the caret notation of a 7 bit byte, printable bytes are unchanged.
+fn render_caret(byte: u8) -> String {
+    match byte {
+        0x00..=0x1f => format!("^{}", (byte + 0x40) as char),
+        0x7f => String::from("^?"),
+        _ => (byte as char).to_string(),
+    }
+}
+
+/// Returns the text of a line read without its newline.
+///
+/// # Arguments
+///
+/// * `bytes` - Vec<u8> the bytes of the line
+/// * `show_nonprinting` - bool, render the bytes in the notation of `cat -v`
+///
+/// Without `show_nonprinting` the bytes that are not valid UTF-8 are replaced
+/// with `U+FFFD`, so no line is ever dropped. A `\r` before the newline is
+/// kept, only `show_nonprinting` renders it as `^M`.
+fn decode_line(bytes: Vec<u8>, show_nonprinting: bool) -> String {
+    if show_nonprinting {
+        return bytes.into_iter().map(render_nonprinting).collect();
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns the line truncated to `columns` characters.
+///
+/// # Arguments
+///
+/// * `line` - String to be truncated
+/// * `columns` - usize the maximum number of columns of the returned line
+///
+/// Columns are counted in characters, not bytes, so multibyte characters are
+/// never split. Truncated lines end with `…`, which takes the last column.
+fn chop_line(line: &str, columns: usize) -> String {
+    if line.chars().count() <= columns {
+        String::from(line)
+    } else {
+        let mut chopped: String = line.chars().take(columns.saturating_sub(1)).collect();
+        chopped.push('…');
+        chopped
+    }
+}
+
+/// Writes the contents of an input to `handle`.
+///
+/// # Arguments
+///
+/// * `handle` - Write where the output is written
+/// * `name` - The name of the input, used in the headers and footers
+/// * `reader` - BufRead with the contents of the input
+/// * `output_formatter` - OutputFormatter structure containing the formatting parameters
+/// * `next_line_number` - The line number counter, shared by all the inputs
+///
+/// The contents are processed line by line only when a formatting flag
+/// requires it, otherwise the bytes are copied untouched. With -r the lines
+/// are reversed before they are formatted, so -n numbers the lines in the
+/// order they are written. The lines omitted by --skip are dropped before any
+/// other processing, they only advance the line counter with --number-skipped.
+/// Headers and footers are written before and after the contents when
+/// requested, the footer in its own line even if the contents do not end
+/// with a newline.
+fn write_input<R: BufRead, W: Write>(
+    handle: &mut W,
+    name: &str,
+    mut reader: R,
+    output_formatter: &OutputFormatter,
+    next_line_number: &mut u32,
+) -> Result<(), ErrCode> {
+    if output_formatter.file_headers {
+        write_output_line(handle, &format!("==> {} <==", name))?;
+    }
+
+    if output_formatter.skip > 0 {
+        let skipped = match skip_lines(&mut reader, output_formatter.skip) {
+            Ok(skipped) => skipped,
+            Err(err) => {
+                eprintln!("Error {}; when reading `{}`.", err, name);
+                return Err(ErrCode::ErrorCannotOpenFileForReading);
             }
-            Ok(file) => io::BufReader::new(file).lines(),
         };
-        let mut prev_blank = false;
+        if output_formatter.number_skipped {
+            *next_line_number += skipped as u32;
+        }
+    }
+
+    if output_formatter.reverse {
+        let mut contents: Vec<u8> = Vec::new();
+        if let Err(err) = reader.read_to_end(&mut contents) {
+            eprintln!("Error {}; when reading `{}`.", err, name);
+            return Err(ErrCode::ErrorCannotOpenFileForReading);
+        }
+        let reversed = reverse_lines(&contents);
+        write_lines(
+            handle,
+            io::Cursor::new(reversed),
+            output_formatter,
+            next_line_number,
+        )?;
+    } else if !output_formatter.is_line_oriented() {
+        let last_byte = match copy_input(&mut reader, handle) {
+            Ok(last_byte) => last_byte,
+            Err(err) => return Err(write_error(err, "when writing to stdout buffer")),
+        };
+        if output_formatter.file_footers && last_byte.is_some() && last_byte != Some(b'\n') {
+            write_output_line(handle, "")?;
+        }
+    } else {
+        write_lines(handle, reader, output_formatter, next_line_number)?;
+    }
+
+    if output_formatter.file_footers {
+        write_output_line(handle, &format!("<== {} ==>", name))?;
+    }
+    Ok(())
+}
 
-        for line in lines {
-            if let Ok(ok_line) = line {
-                let is_blank = ok_line.trim() == "";
+/// Copies the bytes of `reader` untouched to `handle`, returns the last byte
+/// copied, None if the input is empty.
+fn copy_input<R: BufRead, W: Write>(reader: &mut R, handle: &mut W) -> io::Result<Option<u8>> {
+    let mut last_byte = None;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(last_byte);
+        }
+        handle.write_all(buf)?;
+        last_byte = buf.last().copied();
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
 
-                if !is_blank | (is_blank & !output_formatter.only_non_blank) {
-                    next_line_number += 1;
-                }
+/// Reads and discards up to `count` lines of `reader`, returns the number of
+/// lines discarded, which is smaller than `count` if the input ends first.
+fn skip_lines<R: BufRead>(reader: &mut R, count: usize) -> io::Result<usize> {
+    let mut line: Vec<u8> = Vec::new();
+    for skipped in 0..count {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(skipped);
+        }
+    }
+    Ok(count)
+}
 
-                if output_formatter.squeze_blank & (prev_blank & is_blank) {
+/// Returns the lines of the contents in reverse order.
+///
+/// Every line of the result ends with a newline, even if the last line of the
+/// contents did not.
+fn reverse_lines(contents: &[u8]) -> Vec<u8> {
+    let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+    if contents.is_empty() {
+        return Vec::new();
+    }
+    let mut reversed: Vec<u8> = Vec::with_capacity(contents.len() + 1);
+    for line in contents.split(|b| *b == b'\n').rev() {
+        reversed.extend_from_slice(line);
+        reversed.push(b'\n');
+    }
+    reversed
+}
+
+/// Writes the contents of an input to `handle` line by line, applying the
+/// formatting flags.
+fn write_lines<R: BufRead, W: Write>(
+    handle: &mut W,
+    reader: R,
+    output_formatter: &OutputFormatter,
+    next_line_number: &mut u32,
+) -> Result<(), ErrCode> {
+    let mut prev_blank = false;
+
+    for line in reader.split(b'\n') {
+        let line = line
+            .ok()
+            .map(|bytes| decode_line(bytes, output_formatter.show_nonprinting));
+        if let Some(ok_line) = line {
+            // The lines dropped by --grep-out are not numbered or squeezed
+            if let Some(re) = &output_formatter.grep_out {
+                if re.is_match(&ok_line) {
                     continue;
                 }
-                prev_blank = is_blank;
-
-                match writeln!(
-                    handle,
-                    "{}",
-                    format_output_line(&ok_line, next_line_number, &output_formatter)
-                ) {
-                    Ok(_) => {}
-                    Err(err) => {
-                        eprintln!("Error {}; when writing to stdout buffer.", err);
-                        process::exit(ErrCode::ErrorWriteToStdout as i32);
-                    }
-                }
             }
-            match handle.flush() {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("Error {}; when flushing to stdout.", err);
-                    process::exit(ErrCode::ErrorWriteToStdout as i32);
-                }
+
+            // Only the first blank line of a run is written with -s, the
+            // suppressed lines are not numbered
+            let is_squeezable = is_squeezable(&ok_line, output_formatter.blank_is_whitespace);
+            if output_formatter.squeze_blank & (prev_blank & is_squeezable) {
+                continue;
+            }
+            prev_blank = is_squeezable;
+
+            // The lines written without a number, like the empty lines of -b,
+            // do not advance the counter
+            if is_numbered(&ok_line, output_formatter) {
+                *next_line_number += 1;
             }
+
+            let mut output_line = format_output_line(&ok_line, *next_line_number, output_formatter);
+            if let Some(columns) = output_formatter.chop {
+                output_line = chop_line(&output_line, columns);
+            }
+
+            write_output_line(handle, &output_line)?;
         }
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod cat_ts {
-    use super::*;
+/// Writes a line followed by a newline to `handle`.
+fn write_output_line<W: Write>(handle: &mut W, line: &str) -> Result<(), ErrCode> {
+    match writeln!(handle, "{}", line) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(write_error(err, "when writing to stdout buffer")),
+    }
+}
 
-    #[test]
-    fn ts_format_output_line() {
-        let input_string = String::from("my test string");
-        let mut output_formatter = OutputFormatter::new();
+/// Returns the error code of a failed write to the output.
+///
+/// A broken pipe is the normal end of `cat file | head`, it is returned
+/// without an error message so `main` can exit quietly. Other errors are
+/// reported with the `action` that failed.
+fn write_error(err: io::Error, action: &str) -> ErrCode {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        ErrCode::ErrorBrokenPipe
+    } else {
+        eprintln!("Error {}; {}.", err, action);
+        ErrCode::ErrorWriteToStdout
+    }
+}
 
-        // No processing - input matches output
-        assert_eq!(
-            input_string,
-            format_output_line(&input_string, 0, &output_formatter)
-        );
+/// The lines and bytes written for one input.
+#[derive(Debug, PartialEq)]
+struct FileStats {
+    name: String,
+    lines: usize,
+    bytes: usize,
+}
 
-        // Add line number
-        let string_with_number = String::from("12   : my test string");
-        output_formatter.has_line_numbers = true;
-        assert_eq!(
-            string_with_number,
-            format_output_line(&input_string, 12, &output_formatter)
-        );
+impl FileStats {
+    /// Starts the count of an input from the counters of the output.
+    fn start<W: Write>(name: &str, handle: &CountingWriter<W>) -> FileStats {
+        FileStats {
+            name: String::from(name),
+            lines: handle.lines,
+            bytes: handle.bytes,
+        }
+    }
 
-        // Add line number to empty line
-        let empty_string_with_number = String::from("13   :");
-        output_formatter.has_line_numbers = true;
-        assert_eq!(
-            empty_string_with_number,
-            format_output_line(&String::from(""), 13, &output_formatter)
-        );
+    /// Ends the count of an input, keeping what was written since `start`.
+    fn finish<W: Write>(mut self, handle: &CountingWriter<W>) -> FileStats {
+        self.lines = handle.lines - self.lines;
+        self.bytes = handle.bytes - self.bytes;
+        self
+    }
+}
 
-        // Ignore empty lines
-        let empty_string = String::from("");
-        let empty_string_no_number = String::from("     :");
-        output_formatter.only_non_blank = true;
-        output_formatter.has_line_numbers = true;
-        assert_eq!(
-            empty_string_no_number,
-            format_output_line(&empty_string, 14, &output_formatter)
-        );
+/// The output written by `write_inputs`, per input and in total.
+///
+/// The totals include the separators and the final newline, which do not
+/// belong to any input.
+#[derive(Debug, PartialEq)]
+struct Stats {
+    files: Vec<FileStats>,
+    lines: usize,
+    bytes: usize,
+}
+
+/// Writes the --stats summary to `handle`, the standard error in `main`.
+fn write_stats<W: Write>(handle: &mut W, stats: &Stats) -> io::Result<()> {
+    for file in &stats.files {
+        writeln!(
+            handle,
+            "rcat: {}: {} lines, {} bytes",
+            file.name, file.lines, file.bytes
+        )?;
     }
+    writeln!(
+        handle,
+        "rcat: total: {} lines, {} bytes, {} files",
+        stats.lines,
+        stats.bytes,
+        stats.files.len()
+    )
+}
 
-    #[test]
-    fn ts_output_formatter_new() {
-        let of = OutputFormatter::new();
-        assert_eq!(false, of.only_non_blank);
-        assert_eq!(false, of.has_line_numbers);
-        assert_eq!(false, of.ignore_errors);
-        assert_eq!(false, of.squeze_blank);
-        assert_eq!(0usize, of.inputs.len());
+/// A writer that counts the lines and bytes written to the inner writer and
+/// remembers the last one.
+struct CountingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    last_byte: Option<u8>,
+    lines: usize,
+    bytes: usize,
+}
+
+impl<'a, W: Write> CountingWriter<'a, W> {
+    fn new(inner: &'a mut W) -> CountingWriter<'a, W> {
+        CountingWriter {
+            inner,
+            last_byte: None,
+            lines: 0,
+            bytes: 0,
+        }
     }
+}
 
-    #[test]
-    fn ts_read_arguments() {
-        // read inputs into vector
-        let inputs = vec!["cat", "f1", "f2", "f3"];
+impl<'a, W: Write> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.last_byte = Some(buf[written - 1]);
+            self.bytes += written;
+            self.lines += buf[..written].iter().filter(|b| **b == b'\n').count();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the lines of the inputs in turns, a line of every input in every
+/// turn. The inputs that end are left out of the next turns, so the last
+/// lines are the ones of the longest input.
+fn interleave_lines<I: Iterator<Item = Vec<u8>>>(
+    mut inputs: Vec<I>,
+) -> impl Iterator<Item = Vec<u8>> {
+    let mut next = 0;
+    std::iter::from_fn(move || {
+        while !inputs.is_empty() {
+            if next >= inputs.len() {
+                next = 0;
+            }
+            match inputs[next].next() {
+                Some(line) => {
+                    next += 1;
+                    return Some(line);
+                }
+                None => {
+                    inputs.remove(next);
+                }
+            }
+        }
+        None
+    })
+}
+
+/// A reader over the lines of an iterator, every line is followed by a
+/// newline.
+struct LinesReader<I: Iterator<Item = Vec<u8>>> {
+    lines: I,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> LinesReader<I> {
+    fn new(lines: I) -> LinesReader<I> {
+        LinesReader {
+            lines,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> io::Read for LinesReader<I> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(out.len());
+            out[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> BufRead for LinesReader<I> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.buf.len() {
+            if let Some(mut line) = self.lines.next() {
+                line.push(b'\n');
+                self.buf = line;
+                self.pos = 0;
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Opens an input for reading, `-` is the standard input.
+///
+/// Returns None for the inputs skipped with -i, the files that can not be
+/// opened and the directories.
+fn open_input(
+    file_path: &Path,
+    output_formatter: &OutputFormatter,
+) -> Result<Option<Box<dyn BufRead>>, ErrCode> {
+    if file_path == Path::new("-") {
+        Ok(Some(Box::new(io::stdin().lock())))
+    } else if file_path.is_dir() {
+        eprintln!("rcat: {}: Is a directory", file_path.display());
+        if output_formatter.ignore_errors {
+            Ok(None)
+        } else {
+            Err(ErrCode::ErrorInputIsADirectory)
+        }
+    } else {
+        match File::open(file_path) {
+            Err(err_code) => {
+                eprintln!(
+                    "ERROR opening file `{}` for reading: {}",
+                    file_path.display(),
+                    err_code
+                );
+                if output_formatter.ignore_errors {
+                    Ok(None)
+                } else {
+                    Err(ErrCode::ErrorCannotOpenFileForReading)
+                }
+            }
+            Ok(file) => Ok(Some(Box::new(io::BufReader::new(file)))),
+        }
+    }
+}
+
+/// Returns the name of an input in the headers, footers and statistics.
+fn input_name(file_path: &Path) -> String {
+    if file_path == Path::new("-") {
+        String::from("standard input")
+    } else {
+        file_path.display().to_string()
+    }
+}
+
+/// Writes the lines of all the inputs to `handle` in turns with
+/// `interleave_lines`, the inputs are read at the same time.
+///
+/// The merged lines are formatted like the lines of a single input, every
+/// line ends with a newline. Returns the statistics of the merged lines, named
+/// after all the inputs.
+fn write_interleaved<W: Write>(
+    handle: &mut CountingWriter<W>,
+    file_paths: &[&Path],
+    output_formatter: &OutputFormatter,
+    next_line_number: &mut u32,
+) -> Result<FileStats, ErrCode> {
+    let mut readers: Vec<Box<dyn BufRead>> = Vec::with_capacity(file_paths.len());
+    let mut names: Vec<String> = Vec::with_capacity(file_paths.len());
+    for file_path in file_paths {
+        if let Some(reader) = open_input(file_path, output_formatter)? {
+            readers.push(reader);
+            names.push(input_name(file_path));
+        }
+    }
+
+    let lines = readers
+        .into_iter()
+        .map(|reader| reader.split(b'\n').map_while(Result::ok))
+        .collect();
+    let name = names.join(", ");
+    let file_stats = FileStats::start(&name, handle);
+    write_input(
+        handle,
+        &name,
+        LinesReader::new(interleave_lines(lines)),
+        output_formatter,
+        next_line_number,
+    )?;
+    Ok(file_stats.finish(handle))
+}
+
+/// Writes the contents of all the inputs to `handle`.
+///
+/// # Arguments
+///
+/// * `handle` - Write where the output is written
+/// * `file_paths` - The paths of the inputs, `-` is the standard input
+/// * `output_formatter` - OutputFormatter structure containing the formatting parameters
+///
+/// The files that can not be opened and the directories are skipped with -i. The file separator
+/// is written between the contents of consecutive inputs, never before the
+/// first or after the last one, and always in its own line, even if the
+/// previous input does not end with a newline. With --interleave the lines of the inputs are
+/// written in turns.
+///
+/// With --ensure-final-newline a newline is appended when the output does not
+/// end with one. The handle is flushed once, after the last input. A buffered
+/// handle only writes to the output when its buffer is full.
+///
+/// Returns the number of lines and bytes written and the number of inputs
+/// read.
+fn write_inputs<W: Write>(
+    handle: &mut W,
+    file_paths: &[&Path],
+    output_formatter: &OutputFormatter,
+) -> Result<Stats, ErrCode> {
+    let mut next_line_number = 0u32;
+    let mut is_first = true;
+    let mut files: Vec<FileStats> = Vec::new();
+    let mut handle = CountingWriter::new(handle);
+
+    if output_formatter.interleave {
+        files.push(write_interleaved(
+            &mut handle,
+            file_paths,
+            output_formatter,
+            &mut next_line_number,
+        )?);
+    } else {
+        for file_path in file_paths {
+            let reader = match open_input(file_path, output_formatter)? {
+                Some(reader) => reader,
+                None => continue,
+            };
+
+            if let (false, Some(separator)) = (is_first, &output_formatter.file_separator) {
+                if handle.last_byte.is_some() && handle.last_byte != Some(b'\n') {
+                    write_output_line(&mut handle, "")?;
+                }
+                write_output_line(&mut handle, separator)?;
+            }
+            is_first = false;
+
+            let name = input_name(file_path);
+            let file_stats = FileStats::start(&name, &handle);
+            write_input(
+                &mut handle,
+                &name,
+                reader,
+                output_formatter,
+                &mut next_line_number,
+            )?;
+            files.push(file_stats.finish(&handle));
+        }
+    }
+
+    if output_formatter.ensure_final_newline
+        && handle.last_byte.is_some()
+        && handle.last_byte != Some(b'\n')
+    {
+        write_output_line(&mut handle, "")?;
+    }
+
+    match handle.flush() {
+        Ok(_) => Ok(Stats {
+            files,
+            lines: handle.lines,
+            bytes: handle.bytes,
+        }),
+        Err(err) => Err(write_error(err, "when flushing to stdout")),
+    }
+}
+
+fn main() {
+    let output_formatter = read_arguments(env::args_os());
+
+    let file_paths = match get_file_paths(&output_formatter.inputs, output_formatter.ignore_errors)
+    {
+        Ok(file_paths) => file_paths,
+        Err(rc) => {
+            process::exit(rc as i32);
+        }
+    };
+
+    // For every file read the contents
+    let stdout = io::stdout();
+    let mut handle = io::BufWriter::new(stdout);
+
+    match write_inputs(&mut handle, &file_paths, &output_formatter) {
+        Ok(stats) => {
+            if output_formatter.stats {
+                let _ = write_stats(&mut io::stderr(), &stats);
+            }
+        }
+        Err(ErrCode::ErrorBrokenPipe) => process::exit(0),
+        Err(rc) => process::exit(rc as i32),
+    }
+}
+
+#[cfg(test)]
+mod cat_ts {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// Writes the files with their contents in a temporary directory.
+    ///
+    /// Returns the directory, removed when dropped, and the paths of the files.
+    fn write_files(files: &[(&str, &str)]) -> (TempDir, Vec<PathBuf>) {
+        let dir = TempDir::new().unwrap();
+        let mut paths = Vec::new();
+        for (name, contents) in files {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            paths.push(path);
+        }
+        (dir, paths)
+    }
+
+    /// Runs `write_input` with the arguments over the contents of a single
+    /// input and returns the output.
+    fn cat(args: Vec<&str>, contents: &str) -> String {
+        let output_formatter = read_arguments(args);
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(contents.as_bytes());
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn ts_format_output_line() {
+        let input_string = String::from("my test string");
+        let mut output_formatter = OutputFormatter::new();
+
+        // No processing - input matches output
+        assert_eq!(
+            input_string,
+            format_output_line(&input_string, 0, &output_formatter)
+        );
+
+        // Trailing whitespace is preserved
+        let trailing = String::from("line with trailing  ");
+        assert_eq!(
+            trailing,
+            format_output_line(&trailing, 0, &output_formatter)
+        );
+
+        // Add line number
+        let string_with_number = String::from("12   : my test string");
+        output_formatter.has_line_numbers = true;
+        assert_eq!(
+            string_with_number,
+            format_output_line(&input_string, 12, &output_formatter)
+        );
+
+        assert_eq!(
+            "12   : line with trailing  ",
+            format_output_line(&trailing, 12, &output_formatter)
+        );
+
+        // Add line number to empty line
+        let empty_string_with_number = String::from("13   :");
+        output_formatter.has_line_numbers = true;
+        assert_eq!(
+            empty_string_with_number,
+            format_output_line(&String::from(""), 13, &output_formatter)
+        );
+
+        // Ignore empty lines
+        let empty_string = String::from("");
+        let empty_string_no_number = String::from("     :");
+        output_formatter.only_non_blank = true;
+        output_formatter.has_line_numbers = true;
+        assert_eq!(
+            empty_string_no_number,
+            format_output_line(&empty_string, 14, &output_formatter)
+        );
+    }
+
+    #[test]
+    fn ts_format_output_line_number_width() {
+        let output_formatter = read_arguments(vec!["cat", "-n", "--number-width", "8", "f1"]);
+        assert_eq!(8, output_formatter.number_width);
+        assert_eq!(
+            "12      : lorem",
+            format_output_line(&String::from("lorem"), 12, &output_formatter)
+        );
+        assert_eq!(
+            "123456789: lorem",
+            format_output_line(&String::from("lorem"), 123456789, &output_formatter)
+        );
+
+        let output_formatter = read_arguments(vec!["cat", "-b", "--number-width=8", "f1"]);
+        assert_eq!(
+            "        :",
+            format_output_line(&String::from(""), 1, &output_formatter)
+        );
+
+        assert_eq!(5, OutputFormatter::new().number_width);
+    }
+
+    #[test]
+    fn ts_format_output_line_show_ends() {
+        let mut output_formatter = OutputFormatter::new();
+        output_formatter.show_ends = true;
+
+        assert_eq!(
+            "lorem$",
+            format_output_line(&String::from("lorem"), 1, &output_formatter)
+        );
+        // a blank line is a `$` alone
+        assert_eq!(
+            "$",
+            format_output_line(&String::from(""), 1, &output_formatter)
+        );
+        // trailing whitespace is kept
+        assert_eq!(
+            "lorem \t$",
+            format_output_line(&String::from("lorem \t"), 1, &output_formatter)
+        );
+
+        // composes with -n
+        output_formatter.has_line_numbers = true;
+        assert_eq!(
+            "3    : lorem  $",
+            format_output_line(&String::from("lorem  "), 3, &output_formatter)
+        );
+        assert_eq!(
+            "4    :$",
+            format_output_line(&String::from(""), 4, &output_formatter)
+        );
+
+        let of = read_arguments(vec!["cat", "-E", "f1"]);
+        assert!(of.show_ends);
+        assert!(of.is_line_oriented());
+    }
+
+    #[test]
+    fn ts_format_output_line_show_tabs() {
+        let mut output_formatter = OutputFormatter::new();
+        output_formatter.show_tabs = true;
+        assert_eq!(
+            "a^Ib^Ic",
+            format_output_line(&String::from("a\tb\tc"), 1, &output_formatter)
+        );
+        // a trailing tab is not trimmed
+        assert_eq!(
+            "a^I",
+            format_output_line(&String::from("a\t"), 1, &output_formatter)
+        );
+
+        // composes with -n and -E, the number is padded to the tab stop
+        output_formatter.has_line_numbers = true;
+        output_formatter.show_ends = true;
+        assert_eq!(
+            "2    :  a^Ib $",
+            format_output_line(&String::from("a\tb "), 2, &output_formatter)
+        );
+
+        let of = read_arguments(vec!["cat", "-T", "f1"]);
+        assert!(of.show_tabs);
+        assert!(of.is_line_oriented());
+    }
+
+    #[test]
+    fn ts_format_output_line_number_show_tabs() {
+        // the contents start at the same tab stop with numbers of 1 to 3 digits
+        let mut output_formatter = read_arguments(vec!["cat", "-n", "-T", "f1"]);
+        output_formatter.number_width = 1;
+        let lines: Vec<String> = [9, 10, 100]
+            .iter()
+            .map(|n| format_output_line(&String::from("a\tb"), *n, &output_formatter))
+            .collect();
+        assert_eq!("9:      a^Ib", lines[0]);
+        assert_eq!("10:     a^Ib", lines[1]);
+        assert_eq!("100:    a^Ib", lines[2]);
+        for line in &lines {
+            assert_eq!(Some(TAB_WIDTH), line.find('a'));
+        }
+
+        // the default width is padded to the first tab stop
+        let output_formatter = read_arguments(vec!["cat", "-n", "-T", "f1"]);
+        assert_eq!(
+            "1    :  ^Ia",
+            format_output_line(&String::from("\ta"), 1, &output_formatter)
+        );
+        assert_eq!(
+            "12345:  a",
+            format_output_line(&String::from("a"), 12345, &output_formatter)
+        );
+    }
+
+    #[test]
+    fn ts_render_nonprinting() {
+        assert_eq!("^@", render_nonprinting(0x00));
+        assert_eq!("^A", render_nonprinting(0x01));
+        assert_eq!("^?", render_nonprinting(0x7f));
+        assert_eq!("M-A", render_nonprinting(0xc1));
+        assert_eq!("M-^@", render_nonprinting(0x80));
+        assert_eq!("M-^?", render_nonprinting(0xff));
+        assert_eq!("a", render_nonprinting(b'a'));
+
+        // left to -T and -E
+        assert_eq!("\t", render_nonprinting(b'\t'));
+        assert_eq!("\n", render_nonprinting(b'\n'));
+    }
+
+    #[test]
+    fn ts_write_input_show_nonprinting() {
+        let output_formatter = read_arguments(vec!["cat", "-v", "-T", "f1"]);
+        assert!(output_formatter.show_nonprinting);
+
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(b"a\x00b\tc\r\n\xc3\xb1\x7f\n");
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!("a^@b^Ic^M\nM-CM-1^?\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_read_arguments_show_all() {
+        let of = read_arguments(vec!["rcat", "-A", "f"]);
+        assert!(of.show_nonprinting);
+        assert!(of.show_ends);
+        assert!(of.show_tabs);
+
+        // same output as the three flags
+        let contents: &[u8] = b"a\tb \x01\r\n\n\xffc\n";
+        let mut outputs: Vec<Vec<u8>> = Vec::new();
+        for args in [vec!["rcat", "-A", "f"], vec!["rcat", "-v", "-E", "-T", "f"]].iter() {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            write_input(&mut output, "f", io::Cursor::new(contents), &of, &mut 0).unwrap();
+            outputs.push(output);
+        }
+        assert_eq!(
+            "a^Ib ^A^M$\n$\nM-^?c$\n",
+            String::from_utf8(outputs[0].clone()).unwrap()
+        );
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn ts_write_inputs_file_separator() {
+        let (_dir, paths) = write_files(&[("f1", "lorem\n"), ("f2", "ipsum\n"), ("f3", "dolor\n")]);
+        let file_paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+
+        let output_formatter = read_arguments(vec!["cat", "--file-separator=---", "f1"]);
+        let mut output: Vec<u8> = Vec::new();
+        write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!("lorem\n---\nipsum\n---\ndolor\n", output);
+        assert_eq!(2, output.matches("---").count());
+
+        // no separator by default
+        let mut output: Vec<u8> = Vec::new();
+        write_inputs(&mut output, &file_paths, &OutputFormatter::new()).unwrap();
+        assert_eq!("lorem\nipsum\ndolor\n", String::from_utf8(output).unwrap());
+
+        // the separator is written in its own line after a last line without
+        // a newline
+        let (_dir, paths) = write_files(&[("f1", "lorem\nipsum"), ("f2", ""), ("f3", "dolor")]);
+        let file_paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+        let mut output: Vec<u8> = Vec::new();
+        write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        assert_eq!(
+            "lorem\nipsum\n---\n---\ndolor",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn ts_interleave_lines() {
+        let lines = |contents: &'static str| contents.lines().map(|l| l.as_bytes().to_vec());
+        let merged: Vec<Vec<u8>> =
+            interleave_lines(vec![lines("a1\na2\na3"), lines("b1\nb2\nb3")]).collect();
+        assert_eq!(
+            vec!["a1", "b1", "a2", "b2", "a3", "b3"],
+            merged
+                .iter()
+                .map(|l| std::str::from_utf8(l).unwrap())
+                .collect::<Vec<&str>>()
+        );
+
+        // the inputs that end are left out
+        let merged: Vec<Vec<u8>> = interleave_lines(vec![
+            lines("a1"),
+            lines(""),
+            lines("c1\nc2\nc3"),
+            lines("d1\nd2"),
+        ])
+        .collect();
+        assert_eq!(
+            vec!["a1", "c1", "d1", "c2", "d2", "c3"],
+            merged
+                .iter()
+                .map(|l| std::str::from_utf8(l).unwrap())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(
+            0,
+            interleave_lines(Vec::<std::vec::IntoIter<Vec<u8>>>::new()).count()
+        );
+    }
+
+    #[test]
+    fn ts_write_inputs_interleave() {
+        let (_dir, paths) = write_files(&[("f1", "a1\na2\na3\n"), ("f2", "b1\nb2\nb3")]);
+        let file_paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+
+        let output_formatter = read_arguments(vec!["cat", "--interleave", "f1", "f2"]);
+        assert!(output_formatter.interleave);
+        let mut output: Vec<u8> = Vec::new();
+        let stats = write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        assert_eq!(
+            "a1\nb1\na2\nb2\na3\nb3\n",
+            String::from_utf8(output).unwrap()
+        );
+        assert_eq!(1, stats.files.len());
+        assert_eq!(6, stats.files[0].lines);
+
+        // the merged lines are numbered in the order they are written
+        let output_formatter = read_arguments(vec!["cat", "--interleave", "-n", "f1", "f2"]);
+        let mut output: Vec<u8> = Vec::new();
+        write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!("1    : a1", output.lines().next().unwrap());
+        assert_eq!("6    : b3", output.lines().last().unwrap());
+    }
+
+    #[test]
+    fn ts_write_inputs_stats() {
+        let (_dir, paths) = write_files(&[("f1", "lorem\nipsum\n"), ("f2", "dolor\n")]);
+        let file_paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+
+        let output_formatter = read_arguments(vec!["cat", "--stats", "f1", "f2"]);
+        assert!(output_formatter.stats);
+        let mut output: Vec<u8> = Vec::new();
+        let stats = write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        let names: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        assert_eq!(
+            Stats {
+                files: vec![
+                    FileStats {
+                        name: names[0].clone(),
+                        lines: 2,
+                        bytes: 12
+                    },
+                    FileStats {
+                        name: names[1].clone(),
+                        lines: 1,
+                        bytes: 6
+                    },
+                ],
+                lines: 3,
+                bytes: 18,
+            },
+            stats
+        );
+
+        // the summary is not written with the contents
+        assert_eq!("lorem\nipsum\ndolor\n", String::from_utf8(output).unwrap());
+        let mut stderr: Vec<u8> = Vec::new();
+        write_stats(&mut stderr, &stats).unwrap();
+        assert_eq!(
+            format!(
+                "rcat: {}: 2 lines, 12 bytes\nrcat: {}: 1 lines, 6 bytes\nrcat: total: 3 lines, 18 bytes, 2 files\n",
+                names[0], names[1]
+            ),
+            String::from_utf8(stderr).unwrap()
+        );
+    }
+
+    #[test]
+    fn ts_write_inputs_stats_separator() {
+        let (_dir, paths) = write_files(&[("f1", "lorem\n"), ("f2", "ipsum")]);
+        let file_paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+
+        // the separator and the final newline only count in the total
+        let args = vec!["cat", "--file-separator=-", "--ensure-final-newline", "f1"];
+        let output_formatter = read_arguments(args);
+        let mut output: Vec<u8> = Vec::new();
+        let stats = write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        assert_eq!("lorem\n-\nipsum\n", String::from_utf8(output).unwrap());
+        let counts: Vec<(usize, usize)> = stats.files.iter().map(|f| (f.lines, f.bytes)).collect();
+        assert_eq!(vec![(1, 6), (0, 5)], counts);
+        assert_eq!((3, 14), (stats.lines, stats.bytes));
+    }
+
+    /// A writer that counts the calls to flush.
+    struct FlushCounter {
+        output: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    /// A writer that fails with the given kind of error.
+    struct FailingWriter {
+        kind: io::ErrorKind,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(self.kind, "failing writer"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(self.kind, "failing writer"))
+        }
+    }
+
+    #[test]
+    fn ts_write_input_broken_pipe() {
+        for args in [vec!["cat", "f1"], vec!["cat", "-n", "f1"]].iter() {
+            let output_formatter = read_arguments(args);
+            let mut handle = FailingWriter {
+                kind: io::ErrorKind::BrokenPipe,
+            };
+            let reader = io::Cursor::new(b"lorem\n");
+            let result = write_input(&mut handle, "f1", reader, &output_formatter, &mut 0);
+            assert_eq!(Err(ErrCode::ErrorBrokenPipe), result);
+        }
+
+        // the other errors are failures
+        let mut handle = FailingWriter {
+            kind: io::ErrorKind::Other,
+        };
+        let reader = io::Cursor::new(b"lorem\n");
+        let result = write_input(&mut handle, "f1", reader, &OutputFormatter::new(), &mut 0);
+        assert_eq!(Err(ErrCode::ErrorWriteToStdout), result);
+
+        // flushing
+        let mut handle = FailingWriter {
+            kind: io::ErrorKind::BrokenPipe,
+        };
+        let result = write_inputs(&mut handle, &[], &OutputFormatter::new());
+        assert_eq!(Err(ErrCode::ErrorBrokenPipe), result);
+    }
+
+    #[test]
+    fn ts_write_inputs_single_flush() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("f1");
+        let contents: String = (0..100_000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let file_paths = vec![path.as_path(), path.as_path()];
+
+        // the line oriented path flushes once for all the lines and files
+        let output_formatter = read_arguments(vec!["cat", "-n", "f1"]);
+        let mut handle = FlushCounter {
+            output: Vec::new(),
+            flushes: 0,
+        };
+        write_inputs(&mut handle, &file_paths, &output_formatter).unwrap();
+        assert_eq!(1, handle.flushes);
+        let output = String::from_utf8(handle.output).unwrap();
+        assert_eq!(200_000, output.lines().count());
+        assert_eq!("200000: line 99999", output.lines().last().unwrap());
+    }
+
+    #[test]
+    fn ts_write_inputs_number_nonblank() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let f1 = dir.path().join("f1");
+        std::fs::write(&f1, "lorem\n\nipsum\n").unwrap();
+        let f2 = dir.path().join("f2");
+        std::fs::write(&f2, "\ndolor\n \nsit\n").unwrap();
+        let file_paths = vec![f1.as_path(), f2.as_path()];
+
+        let cat = |args: Vec<&str>| {
+            let output_formatter = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // the second file continues the numbering, blank lines are not numbered
+        assert_eq!(
+            "1    : lorem\n     :\n2    : ipsum\n     :\n3    : dolor\n4    :  \n5    : sit\n",
+            cat(vec!["cat", "-b", "f1"])
+        );
+        assert_eq!(
+            "1    : lorem\n2    :\n3    : ipsum\n4    :\n5    : dolor\n6    :  \n7    : sit\n",
+            cat(vec!["cat", "-n", "f1"])
+        );
+    }
+
+    #[test]
+    fn ts_write_input_grep_out() {
+        let output_formatter = read_arguments(vec!["cat", "-n", "--grep-out=^#", "f1"]);
+        assert!(output_formatter.is_line_oriented());
+        let reader = io::Cursor::new(b"# comment\nlorem\n# another\nipsum\n\n#\ndolor\n");
+        let mut output: Vec<u8> = Vec::new();
+        let mut next_line_number = 0;
+        write_input(
+            &mut output,
+            "f1",
+            reader,
+            &output_formatter,
+            &mut next_line_number,
+        )
+        .unwrap();
+        assert_eq!(
+            "1    : lorem\n2    : ipsum\n3    :\n4    : dolor\n",
+            String::from_utf8(output).unwrap()
+        );
+        assert_eq!(4, next_line_number);
+
+        // without numbers
+        let output_formatter = read_arguments(vec!["cat", "--grep-out", "o", "f1"]);
+        let reader = io::Cursor::new(b"lorem\nipsum\ndolor\nsit\n");
+        let mut output: Vec<u8> = Vec::new();
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!("ipsum\nsit\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_write_input_number_matching() {
+        let output_formatter = read_arguments(vec!["cat", "--number-matching", "[0-9]", "f1"]);
+        assert!(output_formatter.has_line_numbers);
+        assert!(output_formatter.is_line_oriented());
+        let reader = io::Cursor::new(b"lorem 1\nipsum\n\ndolor 22\nsit 3\n");
+        let mut output: Vec<u8> = Vec::new();
+        let mut next_line_number = 0;
+        write_input(
+            &mut output,
+            "f1",
+            reader,
+            &output_formatter,
+            &mut next_line_number,
+        )
+        .unwrap();
+        assert_eq!(
+            "1    : lorem 1\n     : ipsum\n     :\n2    : dolor 22\n3    : sit 3\n",
+            String::from_utf8(output).unwrap()
+        );
+        assert_eq!(3, next_line_number);
+
+        assert!(!is_numbered("ipsum", &output_formatter));
+        assert!(is_numbered("ipsum", &OutputFormatter::new()));
+    }
+
+    #[test]
+    fn ts_write_input_skip() {
+        let contents = "name,value\n----\nlorem,1\nipsum,2\n";
+        assert_eq!(
+            "lorem,1\nipsum,2\n",
+            cat(vec!["cat", "--skip", "2", "f1"], contents)
+        );
+        assert_eq!("", cat(vec!["cat", "--skip=5", "f1"], contents));
+        assert_eq!(contents, cat(vec!["cat", "--skip=0", "f1"], contents));
+
+        // the numbering starts after the skipped lines unless they are counted
+        assert_eq!(
+            "1    : lorem,1\n2    : ipsum,2\n",
+            cat(vec!["cat", "-n", "--skip=2", "f1"], contents)
+        );
+        assert_eq!(
+            "3    : lorem,1\n4    : ipsum,2\n",
+            cat(
+                vec!["cat", "-n", "--skip=2", "--number-skipped", "f1"],
+                contents
+            )
+        );
+
+        // the skip applies to the input before it is reversed
+        assert_eq!(
+            "ipsum,2\nlorem,1\n",
+            cat(vec!["cat", "-r", "--skip=2", "f1"], contents)
+        );
+    }
+
+    #[test]
+    fn ts_write_input_reverse() {
+        assert_eq!("3\n2\n1\n", cat(vec!["cat", "-r", "f1"], "1\n2\n3\n"));
+        assert_eq!("3\n2\n1\n", cat(vec!["cat", "--reverse", "f1"], "1\n2\n3"));
+        assert_eq!("\nb\na\n", cat(vec!["cat", "-r", "f1"], "a\nb\n\n"));
+        assert_eq!("", cat(vec!["cat", "-r", "f1"], ""));
+
+        // the numbers count the written lines
+        assert_eq!(
+            "1    : c\n2    : b\n3    : a\n",
+            cat(vec!["cat", "-r", "-n", "f1"], "a\nb\nc\n")
+        );
+    }
+
+    #[test]
+    fn ts_write_inputs_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("f1");
+        std::fs::write(&file, "lorem\n").unwrap();
+        let file_paths = vec![dir.path(), file.as_path()];
+
+        // the directory is an error
+        let mut output: Vec<u8> = Vec::new();
+        let result = write_inputs(&mut output, &file_paths, &OutputFormatter::new());
+        assert_eq!(Err(ErrCode::ErrorInputIsADirectory), result);
+        assert!(output.is_empty());
+
+        // the directory is skipped with -i
+        let output_formatter = read_arguments(vec!["cat", "-i", "f1"]);
+        let mut output: Vec<u8> = Vec::new();
+        write_inputs(&mut output, &file_paths, &output_formatter).unwrap();
+        assert_eq!("lorem\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_write_inputs_ensure_final_newline() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let no_newline = dir.path().join("f1");
+        std::fs::write(&no_newline, "lorem\nipsum").unwrap();
+        let newline = dir.path().join("f2");
+        std::fs::write(&newline, "dolor\n").unwrap();
+        let empty = dir.path().join("f3");
+        std::fs::write(&empty, "").unwrap();
+
+        let cat = |args: Vec<&str>, file_paths: &[&Path]| {
+            let output_formatter = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            write_inputs(&mut output, file_paths, &output_formatter).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let flag = vec!["cat", "--ensure-final-newline"];
+        assert_eq!("lorem\nipsum\n", cat(flag.clone(), &[no_newline.as_path()]));
+        assert_eq!("lorem\nipsum", cat(vec!["cat"], &[no_newline.as_path()]));
+
+        // only the end of the output is checked
+        assert_eq!(
+            "lorem\nipsumdolor\n",
+            cat(flag.clone(), &[no_newline.as_path(), newline.as_path()])
+        );
+        assert_eq!("dolor\n", cat(flag.clone(), &[newline.as_path()]));
+        assert_eq!("", cat(flag, &[empty.as_path()]));
+    }
+
+    #[test]
+    fn ts_chop_line() {
+        // short lines are not modified
+        assert_eq!("short", chop_line("short", 10));
+        assert_eq!("exact", chop_line("exact", 5));
+
+        // long lines are truncated to the number of columns
+        assert_eq!("a long…", chop_line("a long line", 7));
+
+        // multibyte characters count as a single column and are not split
+        assert_eq!("ñañañ…", chop_line("ñañañañaña", 6));
+        assert_eq!("€€…", chop_line("€€€€", 3));
+    }
+
+    #[test]
+    fn ts_write_input_file_headers() {
+        let mut output_formatter = OutputFormatter::new();
+        output_formatter.file_headers = true;
+        output_formatter.file_footers = true;
+
+        let mut output: Vec<u8> = Vec::new();
+        let mut next_line_number = 0u32;
+        for (name, contents) in [("f1", "lorem\nipsum\n"), ("f2", "dolor\n")].iter() {
+            let reader = io::Cursor::new(contents.as_bytes());
+            write_input(
+                &mut output,
+                name,
+                reader,
+                &output_formatter,
+                &mut next_line_number,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            "==> f1 <==\nlorem\nipsum\n<== f1 ==>\n==> f2 <==\ndolor\n<== f2 ==>\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // only headers
+        output_formatter.file_footers = false;
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(b"lorem\n");
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!("==> f1 <==\nlorem\n", String::from_utf8(output).unwrap());
+
+        // the footer is written in its own line after a last line without a
+        // newline
+        output_formatter.file_headers = false;
+        output_formatter.file_footers = true;
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(b"lorem\nipsum");
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!(
+            "lorem\nipsum\n<== f1 ==>\n",
+            String::from_utf8(output).unwrap()
+        );
+        let mut output: Vec<u8> = Vec::new();
+        write_input(
+            &mut output,
+            "f1",
+            io::Cursor::new(b""),
+            &output_formatter,
+            &mut 0,
+        )
+        .unwrap();
+        assert_eq!("<== f1 ==>\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_write_input_squeeze_blank() {
+        let squeeze = |args: Vec<&str>, contents: &str| {
+            let output_formatter = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            let reader = io::Cursor::new(contents.as_bytes());
+            write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!("a\n\nb\n", squeeze(vec!["cat", "-s"], "a\n\n\n\nb\n"));
+        assert_eq!(
+            "1    : a\n2    :\n3    : b\n",
+            squeeze(vec!["cat", "-s", "-n"], "a\n\n\n\nb\n")
+        );
+
+        // leading and trailing blank lines
+        assert_eq!(
+            "1    :\n2    : a\n3    :\n",
+            squeeze(vec!["cat", "-s", "-n"], "\n\n\na\n\n\n")
+        );
+
+        // a file that is entirely blank
+        assert_eq!("\n", squeeze(vec!["cat", "-s"], "\n\n\n\n"));
+        assert_eq!("1    :\n", squeeze(vec!["cat", "-s", "-n"], "\n\n\n\n"));
+    }
+
+    #[test]
+    fn ts_is_squeezable() {
+        assert!(is_squeezable("", false));
+        assert!(is_squeezable("", true));
+        assert!(!is_squeezable("lorem", true));
+
+        // whitespace only lines are only blank when requested
+        assert!(!is_squeezable(" \t\x0c", false));
+        assert!(is_squeezable(" \t\x0c", true));
+    }
+
+    #[test]
+    fn ts_write_input_squeeze_whitespace() {
+        let contents = "lorem\n\n \t\n\x0c\n\nipsum\n";
+        let squeeze = |args: Vec<&str>| {
+            let output_formatter = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            let reader = io::Cursor::new(contents.as_bytes());
+            write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // only empty lines are squeezed by default
+        assert_eq!(
+            "lorem\n\n \t\n\x0c\n\nipsum\n",
+            squeeze(vec!["cat", "-s", "f1"])
+        );
+        assert_eq!(
+            "lorem\n\nipsum\n",
+            squeeze(vec!["cat", "-s", "--blank-is-whitespace", "f1"])
+        );
+    }
+
+    #[test]
+    fn ts_write_input_numbered_stdin() {
+        // the standard input goes through the same path as the files
+        let output_formatter = read_arguments(vec!["cat", "-n", "-s"]);
+        assert_eq!(vec!["-"], output_formatter.inputs);
+
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(b"lorem\n\n\n\nipsum\n");
+        write_input(&mut output, "-", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!(
+            "1    : lorem\n2    :\n3    : ipsum\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn ts_write_input_binary() {
+        let contents: &[u8] = b"\xff\xfe\r\nlorem\n\x00\xffipsum";
+        let output_formatter = OutputFormatter::new();
+        assert!(!output_formatter.is_line_oriented());
+
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(contents);
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!(contents, output.as_slice());
+    }
+
+    #[test]
+    fn ts_write_input_carriage_return() {
+        assert_eq!("x\r$\n", cat(vec!["cat", "-E", "f1"], "x\r\n"));
+        assert_eq!("x^M$\n", cat(vec!["cat", "-v", "-E", "f1"], "x\r\n"));
+    }
+
+    #[test]
+    fn ts_write_input_invalid_utf8() {
+        let output_formatter = read_arguments(vec!["cat", "-n", "f1"]);
+        let mut output: Vec<u8> = Vec::new();
+        let reader = io::Cursor::new(b"caf\xe9\nok\n");
+        write_input(&mut output, "f1", reader, &output_formatter, &mut 0).unwrap();
+        assert_eq!(
+            "1    : caf\u{FFFD}\n2    : ok\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn ts_output_formatter_new() {
+        let of = OutputFormatter::new();
+        assert_eq!(false, of.only_non_blank);
+        assert_eq!(false, of.has_line_numbers);
+        assert_eq!(false, of.ignore_errors);
+        assert_eq!(false, of.squeze_blank);
+        assert_eq!(None, of.chop);
+        assert_eq!(0usize, of.inputs.len());
+    }
+
+    #[test]
+    fn ts_read_arguments() {
+        // read inputs into vector
+        let inputs = vec!["cat", "f1", "f2", "f3"];
         let mut of = read_arguments(&inputs);
         assert_eq!(inputs.len() - 1, of.inputs.len());
         assert_eq!(false, of.only_non_blank);
@@ -338,5 +1996,15 @@ mod cat_ts {
         assert_eq!(true, of.only_non_blank);
         assert_eq!(true, of.ignore_errors);
         assert_eq!(true, of.squeze_blank);
+
+        // no inputs reads the standard input
+        of = read_arguments(vec!["cat"]);
+        assert_eq!(vec!["-"], of.inputs);
+        of = read_arguments(vec!["cat", "f1", "-", "f2"]);
+        assert_eq!(vec!["f1", "-", "f2"], of.inputs);
+
+        // truncate lines
+        of = read_arguments(vec!["cat", "--chop", "80", "f1"]);
+        assert_eq!(Some(80), of.chop);
     }
 } // mod cat_ts
@@ -7,28 +7,136 @@
  *
  */
 use clap::{App, Arg};
-use regex::Regex;
+use flate2::bufread::MultiGzDecoder;
+use regex::bytes::{Regex, RegexBuilder};
+use std::cell::Cell;
+use std::cmp;
+use std::collections::VecDeque;
 use std::env;
 use std::ffi::OsString;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
-use std::process;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 extern crate toolslib;
-use crate::toolslib::ErrCode;
+use crate::toolslib::{read_all, split_lines_with_terminators, ErrCode};
 
 const VERSION: &str = "ver. 0.0.1";
 
+/// The magic bytes at the start of a gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// ANSI escape sequences used to highlight the matches
+const COLOR_MATCH: &str = "\x1b[01;31m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+#[cfg(test)]
+thread_local! {
+    /// The number of expressions compiled by `build_regex` in the thread
+    static REGEX_BUILDS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// How the files containing binary data are searched.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum BinaryFiles {
+    /// Print a one line message when a binary file matches
+    Binary,
+    /// Process binary files as if they were text
+    Text,
+    /// Assume that binary files do not match
+    WithoutMatch,
+}
+
+/// How the pattern is matched.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Engine {
+    /// Match literally the patterns without regular expression metacharacters
+    Auto,
+    /// Always match the pattern literally
+    Literal,
+    /// Always match the pattern as a regular expression
+    Regex,
+}
+
+/// The file names searched by a recursive search, `--include` and `--exclude`.
+#[derive(Debug, Default)]
+struct NameFilter {
+    /// When not empty only the names matching one of the globs are searched
+    include: Vec<String>,
+    /// The names matching one of the globs are not searched
+    exclude: Vec<String>,
+}
+
+impl NameFilter {
+    /// Returns true if the file `name` has to be searched.
+    fn accepts(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|g| glob_match(g, name)))
+            && !self.exclude.iter().any(|g| glob_match(g, name))
+    }
+}
+
 /// A structure that stores the parsed flags from command line and input files.
 struct OutputFormatter {
     ignore_match: bool,
+    ignore_case: bool,
+    /// The classes and word boundaries of the pattern are ASCII only
+    no_unicode: bool,
+    /// The pattern is matched against the whole input, `-U`
+    multiline: bool,
+    /// `.` matches the line terminators, `--dotall`
+    dotall: bool,
+    word_match: bool,
+    line_match: bool,
     has_line_numbers: bool,
+    /// Print the offset in the input of every line or match, `-b`
+    byte_offset: bool,
     with_file_name: bool,
+    /// Print the file name once before its lines instead of in every line
+    heading: bool,
     only_file_names: bool,
+    /// Print the names of the files without a match, `-L`
+    files_without_match: bool,
     only_line_count: bool,
-    pattern: String,
+    max_count: Option<usize>,
+    max_columns: Option<usize>,
+    /// Number of lines printed before every matching line
+    before_context: usize,
+    /// Number of lines printed after every matching line
+    after_context: usize,
+    /// Print every match instead of the line
+    only_matching: bool,
+    /// Print only this match of every line with `-o`, negative counts from the end
+    nth: Option<i64>,
+    /// Template printed for every match instead of the line
+    replace: Option<String>,
+    passthru: bool,
+    color: bool,
+    recursive: bool,
+    name_filter: NameFilter,
+    sort_paths: bool,
+    decompress: bool,
+    pre: Option<String>,
+    field_separator: Option<String>,
+    report_encoding_issues: bool,
+    /// Report the time spent compiling and scanning, `--stats`
+    stats: bool,
+    count_as_exit: bool,
+    /// Print nothing and stop at the first selected line, `-q`
+    quiet: bool,
+    /// The records of the input and the output end with a NUL byte, `-z`
+    null_data: bool,
+    binary_files: BinaryFiles,
+    engine: Engine,
+    /// The patterns of `-e`, a line is selected if any of them matches
+    patterns: Vec<String>,
+    /// The files of `-f` with one pattern per line
+    pattern_files: Vec<String>,
     inputs: Vec<String>,
 }
 
@@ -37,24 +145,60 @@ impl OutputFormatter {
     fn new(pattern: &str) -> OutputFormatter {
         OutputFormatter {
             ignore_match: false,
+            ignore_case: false,
+            no_unicode: false,
+            multiline: false,
+            dotall: false,
+            word_match: false,
+            line_match: false,
             has_line_numbers: false,
+            byte_offset: false,
             with_file_name: false,
+            heading: false,
             only_file_names: false,
+            files_without_match: false,
             only_line_count: false,
-            pattern: String::from(pattern),
+            max_count: None,
+            max_columns: None,
+            before_context: 0,
+            after_context: 0,
+            only_matching: false,
+            nth: None,
+            replace: None,
+            passthru: false,
+            color: false,
+            recursive: false,
+            name_filter: NameFilter::default(),
+            sort_paths: true,
+            decompress: false,
+            pre: None,
+            field_separator: None,
+            report_encoding_issues: false,
+            stats: false,
+            count_as_exit: false,
+            quiet: false,
+            null_data: false,
+            binary_files: BinaryFiles::Binary,
+            engine: Engine::Auto,
+            patterns: vec![String::from(pattern)],
+            pattern_files: Vec::new(),
             inputs: Vec::new(),
         }
     }
+
+    /// Returns the byte that ends the records of the input and the output.
+    fn terminator(&self) -> u8 {
+        if self.null_data {
+            b'\0'
+        } else {
+            b'\n'
+        }
+    }
 }
 
-/// Read the command line arguments and parse them into the OutputFormatter
-/// structure. Return input files in a vector.
-fn read_arguments<I, T>(itr: I) -> OutputFormatter
-where
-    I: IntoIterator<Item = T>,
-    T: Into<OsString> + Clone,
-{
-    let matches = App::new("grep: grep clone command written in Rust")
+/// Returns the definition of the command line arguments.
+fn build_app<'a, 'b>() -> App<'a, 'b> {
+    App::new("grep: grep clone command written in Rust")
         .version(VERSION)
         .author("Manuel Berrocal")
         .about("searches for patterns in the input text")
@@ -64,17 +208,53 @@ where
                 .takes_value(false)
                 .help("precede each match with the line number in the file (starting at 1)"),
         )
+        .arg(
+            Arg::with_name("byte_offset")
+                .short("b")
+                .long("byte-offset")
+                .takes_value(false)
+                .help("precede each line, or each match with -o, with its byte offset in the file (starting at 0)"),
+        )
         .arg(
             Arg::with_name("ignore_match")
                 .short("v")
                 .takes_value(false)
                 .help("select lines not matching the expression"),
         )
+        .arg(
+            Arg::with_name("ignore_case")
+                .short("i")
+                .long("ignore-case")
+                .takes_value(false)
+                .help("ignore the case of the pattern and the input"),
+        )
+        .arg(
+            Arg::with_name("word_match")
+                .short("w")
+                .long("word-regexp")
+                .takes_value(false)
+                .help("select only the matches that form whole words"),
+        )
+        .arg(
+            Arg::with_name("line_match")
+                .short("x")
+                .long("line-regexp")
+                .takes_value(false)
+                .help("select only the matches of the whole line"),
+        )
         .arg(
             Arg::with_name("with_file_name")
                 .short("H")
                 .takes_value(false)
-                .help("precede each match with input file name"),
+                .help("precede each match with input file name, the default with several inputs"),
+        )
+        .arg(
+            Arg::with_name("no_file_name")
+                .short("h")
+                .long("no-filename")
+                .takes_value(false)
+                .conflicts_with("with_file_name")
+                .help("do not precede the matches with the input file name"),
         )
         .arg(
             Arg::with_name("only_file_names")
@@ -82,18 +262,329 @@ where
                 .takes_value(false)
                 .help("print names of the fileswith content  matching the pattern"),
         )
+        .arg(
+            Arg::with_name("files_without_match")
+                .short("L")
+                .long("files-without-match")
+                .takes_value(false)
+                .conflicts_with_all(&["only_file_names", "only_line_count"])
+                .help("print names of the files without a line matching the pattern"),
+        )
         .arg(
             Arg::with_name("only_line_count")
                 .short("c")
                 .takes_value(false)
-                .help("print only a count of matching lines to standard output"),
+                .help(
+                    "print only a count of matching lines to standard output, \
+                     lines with several matches are counted once",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_count")
+                .short("m")
+                .long("max-count")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("NUM must be a non negative integer")),
+                })
+                .help("stop reading a file after NUM matching lines, also caps the -c count"),
+        )
+        .arg(
+            Arg::with_name("max_columns")
+                .long("max-columns")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("NUM must be a positive integer")),
+                })
+                .help("omit the lines longer than NUM columns"),
+        )
+        .arg(
+            Arg::with_name("after_context")
+                .short("A")
+                .long("after-context")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("NUM must be a non negative integer")),
+                })
+                .help("print NUM lines of context after every matching line"),
+        )
+        .arg(
+            Arg::with_name("before_context")
+                .short("B")
+                .long("before-context")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("NUM must be a non negative integer")),
+                })
+                .help("print NUM lines of context before every matching line"),
+        )
+        .arg(
+            Arg::with_name("context")
+                .short("C")
+                .long("context")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(String::from("NUM must be a non negative integer")),
+                })
+                .help("print NUM lines of context around every matching line"),
+        )
+        .arg(
+            Arg::with_name("only_matching")
+                .short("o")
+                .long("only-matching")
+                .takes_value(false)
+                .conflicts_with("ignore_match")
+                .help("print only the matching part of the lines, every match in its own line"),
+        )
+        .arg(
+            Arg::with_name("nth")
+                .long("nth")
+                .takes_value(true)
+                .value_name("N")
+                .allow_hyphen_values(true)
+                .requires("only_matching")
+                .validator(|v| match v.parse::<i64>() {
+                    Ok(n) if n != 0 => Ok(()),
+                    _ => Err(String::from("N must be a non zero integer")),
+                })
+                .help("with -o print only the Nth match of every line, a negative N counts from the end"),
+        )
+        .arg(
+            Arg::with_name("replace")
+                .long("replace")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .conflicts_with_all(&[
+                    "only_file_names",
+                    "files_without_match",
+                    "only_line_count",
+                    "ignore_match",
+                ])
+                .help("print TEMPLATE for every match, $1, $2... are the capture groups"),
+        )
+        .arg(
+            Arg::with_name("passthru")
+                .long("passthru")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "only_file_names",
+                    "files_without_match",
+                    "only_line_count",
+                    "only_matching",
+                    "replace",
+                ])
+                .help("print all lines; matches are highlighted when color is enabled, except with -v"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .alias("colour")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .value_name("WHEN")
+                .possible_values(&["auto", "always", "never"])
+                .help("highlight the matches: auto, always or never (default), --color alone is auto"),
+        )
+        .arg(
+            Arg::with_name("recursive")
+                .short("r")
+                .long("recursive")
+                .takes_value(false)
+                .help("search the files in the directory arguments recursively"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("GLOB")
+                .help("with -r search only the files whose name matches GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .help("with -r skip the files whose name matches GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .value_name("ORDER")
+                .possible_values(&["path", "none"])
+                .help("order of the files found with -r: path (default) or none"),
+        )
+        .arg(
+            Arg::with_name("decompress")
+                .long("decompress")
+                .takes_value(false)
+                .help("search the contents of gzip compressed inputs"),
+        )
+        .arg(
+            Arg::with_name("pre")
+                .long("pre")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("search the output of COMMAND run with the path of each file"),
+        )
+        .arg(
+            Arg::with_name("field_separator")
+                .long("field-separator")
+                .takes_value(true)
+                .value_name("STR")
+                .help("separate the file name, line number and line with STR"),
+        )
+        .arg(
+            Arg::with_name("engine")
+                .long("engine")
+                .takes_value(true)
+                .value_name("ENGINE")
+                .possible_values(&["auto", "literal", "regex"])
+                .help("how the pattern is matched: auto (default), literal or regex"),
+        )
+        .arg(
+            Arg::with_name("heading")
+                .long("heading")
+                .takes_value(false)
+                .conflicts_with_all(&["only_file_names", "files_without_match", "only_line_count"])
+                .help("print the file name above its matching lines, the files are separated by a blank line"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .alias("silent")
+                .takes_value(false)
+                .help("print nothing, exit with status 0 at the first selected line"),
+        )
+        .arg(
+            Arg::with_name("no_unicode")
+                .long("no-unicode")
+                .takes_value(false)
+                .help(
+                    "match \\w, \\d, \\s, \\b and -i as ASCII only, which is faster; \
+                     . and negated classes match single bytes",
+                ),
+        )
+        .arg(
+            Arg::with_name("multiline")
+                .short("U")
+                .long("multiline")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "ignore_match",
+                    "after_context",
+                    "before_context",
+                    "context",
+                    "passthru",
+                    "null_data",
+                ])
+                .help("match the pattern against the whole input, a match can span several lines"),
+        )
+        .arg(
+            Arg::with_name("dotall")
+                .long("dotall")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "ignore_match",
+                    "after_context",
+                    "before_context",
+                    "context",
+                    "passthru",
+                    "null_data",
+                ])
+                .help("let . match the line terminators, implies -U"),
+        )
+        .arg(
+            Arg::with_name("null_data")
+                .short("z")
+                .long("null-data")
+                .takes_value(false)
+                .help("the input and output records end with a NUL byte instead of a newline"),
+        )
+        .arg(
+            Arg::with_name("fixed_strings")
+                .short("F")
+                .long("fixed-strings")
+                .takes_value(false)
+                .conflicts_with("engine")
+                .help("match the pattern literally, like --engine=literal"),
+        )
+        .arg(
+            Arg::with_name("report_encoding_issues")
+                .long("report-encoding-issues")
+                .takes_value(false)
+                .help("list at the end the files that are not valid UTF-8"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .takes_value(false)
+                .conflicts_with_all(&["only_file_names", "files_without_match", "quiet"])
+                .help("report to stderr the time spent compiling the pattern and scanning the inputs"),
+        )
+        .arg(
+            Arg::with_name("count_as_exit")
+                .long("count-as-exit")
+                .takes_value(false)
+                .help("exit with the number of matching lines, or files with -l, up to 255"),
+        )
+        .arg(
+            Arg::with_name("binary_files")
+                .long("binary-files")
+                .takes_value(true)
+                .value_name("TYPE")
+                .possible_values(&["binary", "text", "without-match"])
+                .conflicts_with_all(&["text", "without_match"])
+                .help("how to handle binary files: binary (default), text or without-match"),
+        )
+        .arg(
+            Arg::with_name("text")
+                .short("a")
+                .long("text")
+                .takes_value(false)
+                .conflicts_with("without_match")
+                .help("process binary files as text, same as --binary-files=text"),
+        )
+        .arg(
+            Arg::with_name("without_match")
+                .short("I")
+                .takes_value(false)
+                .help("assume binary files do not match, same as --binary-files=without-match"),
         )
         .arg(
             Arg::with_name("pattern")
                 .short("e")
-                .help("the pattern to search for")
+                .help("the pattern to search for, can be given several times")
                 .takes_value(true)
-                .required(true),
+                .multiple(true)
+                .number_of_values(1)
+                .required_unless("pattern_file"),
+        )
+        .arg(
+            Arg::with_name("pattern_file")
+                .short("f")
+                .long("file")
+                .value_name("FILE")
+                .help("read the patterns from FILE, one per line, `-` is the standard input")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
         )
         .arg(
             Arg::with_name("inputs")
@@ -102,19 +593,50 @@ where
                 .takes_value(true)
                 .multiple(true),
         )
-        .get_matches_from(itr);
+}
+
+/// Read the command line arguments and parse them into the OutputFormatter
+/// structure. Return input files in a vector.
+fn read_arguments<I, T>(itr: I) -> OutputFormatter
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = build_app().get_matches_from(itr);
 
-    // unwrap is safe as the pattern argument is required
-    let mut output_formatter = OutputFormatter::new(matches.value_of("pattern").unwrap());
+    let mut output_formatter = OutputFormatter::new("");
+    output_formatter.patterns = match matches.values_of("pattern") {
+        Some(patterns) => patterns.map(String::from).collect(),
+        None => Vec::new(),
+    };
+    if let Some(files) = matches.values_of("pattern_file") {
+        output_formatter.pattern_files = files.map(String::from).collect();
+    }
 
     if matches.is_present("ignore_match") {
         output_formatter.ignore_match = true;
     }
 
+    if matches.is_present("ignore_case") {
+        output_formatter.ignore_case = true;
+    }
+
+    if matches.is_present("word_match") {
+        output_formatter.word_match = true;
+    }
+
+    if matches.is_present("line_match") {
+        output_formatter.line_match = true;
+    }
+
     if matches.is_present("line_number") {
         output_formatter.has_line_numbers = true;
     }
 
+    if matches.is_present("byte_offset") {
+        output_formatter.byte_offset = true;
+    }
+
     if matches.is_present("with_file_name") {
         output_formatter.with_file_name = true;
     }
@@ -123,10 +645,150 @@ where
         output_formatter.only_file_names = true;
     }
 
+    if matches.is_present("files_without_match") {
+        output_formatter.files_without_match = true;
+    }
+
+    if matches.is_present("no_unicode") {
+        output_formatter.no_unicode = true;
+    }
+
+    // `.` can only match a line terminator when the lines are not split
+    if matches.is_present("multiline") || matches.is_present("dotall") {
+        output_formatter.multiline = true;
+        output_formatter.dotall = matches.is_present("dotall");
+    }
+
+    if matches.is_present("quiet") {
+        output_formatter.quiet = true;
+    }
+
+    if matches.is_present("null_data") {
+        output_formatter.null_data = true;
+    }
+
     if matches.is_present("only_line_count") {
         output_formatter.only_line_count = true;
     }
 
+    // The validator guarantees that the value parses
+    if let Some(max_count) = matches.value_of("max_count") {
+        output_formatter.max_count = max_count.parse::<usize>().ok();
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(max_columns) = matches.value_of("max_columns") {
+        output_formatter.max_columns = max_columns.parse::<usize>().ok();
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(n) = matches.value_of("nth") {
+        output_formatter.nth = n.parse::<i64>().ok();
+    }
+
+    // The validator guarantees that the values parse, -A and -B take
+    // precedence over -C
+    if let Some(context) = matches.value_of("context") {
+        output_formatter.before_context = context.parse::<usize>().unwrap();
+        output_formatter.after_context = output_formatter.before_context;
+    }
+    if let Some(after_context) = matches.value_of("after_context") {
+        output_formatter.after_context = after_context.parse::<usize>().unwrap();
+    }
+    if let Some(before_context) = matches.value_of("before_context") {
+        output_formatter.before_context = before_context.parse::<usize>().unwrap();
+    }
+
+    if matches.is_present("only_matching") {
+        output_formatter.only_matching = true;
+    }
+
+    if let Some(template) = matches.value_of("replace") {
+        output_formatter.replace = Some(String::from(template));
+    }
+
+    if matches.is_present("passthru") {
+        output_formatter.passthru = true;
+    }
+
+    // The matches are only highlighted with auto when the output is a terminal
+    output_formatter.color = match matches.value_of("color") {
+        Some("always") => true,
+        Some("auto") => io::stdout().is_terminal(),
+        None if matches.is_present("color") => io::stdout().is_terminal(),
+        _ => false,
+    };
+
+    // The matches of a recursive search are preceded by the path of the file
+    if matches.is_present("recursive") {
+        output_formatter.recursive = true;
+        output_formatter.with_file_name = true;
+    }
+
+    if matches.is_present("decompress") {
+        output_formatter.decompress = true;
+    }
+
+    if matches.is_present("report_encoding_issues") {
+        output_formatter.report_encoding_issues = true;
+    }
+
+    if matches.is_present("stats") {
+        output_formatter.stats = true;
+    }
+
+    if matches.is_present("count_as_exit") {
+        output_formatter.count_as_exit = true;
+    }
+
+    if let Some(command) = matches.value_of("pre") {
+        output_formatter.pre = Some(String::from(command));
+    }
+
+    if let Some(separator) = matches.value_of("field_separator") {
+        output_formatter.field_separator = Some(String::from(separator));
+    }
+
+    if let Some(globs) = matches.values_of("include") {
+        output_formatter.name_filter.include = globs.map(String::from).collect();
+    }
+
+    if let Some(globs) = matches.values_of("exclude") {
+        output_formatter.name_filter.exclude = globs.map(String::from).collect();
+    }
+
+    if matches.value_of("sort") == Some("none") {
+        output_formatter.sort_paths = false;
+    }
+
+    if let Some(engine) = matches.value_of("engine") {
+        output_formatter.engine = match engine {
+            "literal" => Engine::Literal,
+            "regex" => Engine::Regex,
+            _ => Engine::Auto,
+        };
+    }
+
+    if matches.is_present("heading") {
+        output_formatter.heading = true;
+    }
+
+    if matches.is_present("fixed_strings") {
+        output_formatter.engine = Engine::Literal;
+    }
+
+    if matches.is_present("text") {
+        output_formatter.binary_files = BinaryFiles::Text;
+    } else if matches.is_present("without_match") {
+        output_formatter.binary_files = BinaryFiles::WithoutMatch;
+    } else if let Some(binary_files) = matches.value_of("binary_files") {
+        output_formatter.binary_files = match binary_files {
+            "text" => BinaryFiles::Text,
+            "without-match" => BinaryFiles::WithoutMatch,
+            _ => BinaryFiles::Binary,
+        };
+    }
+
     if matches.is_present("inputs") {
         let vals: Vec<&str> = matches.values_of("inputs").unwrap().collect();
 
@@ -141,6 +803,15 @@ where
         output_formatter.inputs.push(String::from("-"));
     }
 
+    // The matches are preceded by the file name when several inputs, or the
+    // files of a recursive search, are searched, unless -h is given
+    if output_formatter.inputs.len() > 1 {
+        output_formatter.with_file_name = true;
+    }
+    if matches.is_present("no_file_name") {
+        output_formatter.with_file_name = false;
+    }
+
     output_formatter
 }
 
@@ -152,268 +823,3093 @@ where
 /// * `reader` - A `BufRead` containing the text to match.
 /// * `re` - A RegEx object containing the regular expression
 /// * `ignore_match` - a bool that inverts the matching logic. When `ignore_match`
-///    is true returns the files that do not include a match.
+///   is true the lines that do not match are selected.
+/// * `terminator` - The byte that ends the records, `\n` or NUL with `-z`
 ///
 /// # Return
-/// * Return true if buffer content matches the regular expression.
-/// * Return false if buffer content matches the regular expression and -v flag.
+/// * Return true if a line of the buffer matches the regular expression.
+/// * With -v, return true if a line of the buffer does not match it.
 fn find_match<T: BufRead + Sized>(
     reader: T,
     re: &Regex,
     ignore_match: bool,
+    terminator: u8,
 ) -> Result<bool, ErrCode> {
-    let found = if ignore_match { false } else { true };
-    for line_ in reader.lines() {
-        let line = line_.unwrap();
-        if re.is_match(line.as_str()) {
-            return Ok(found);
+    for line in read_lines(reader, terminator) {
+        if re.is_match(line.as_bytes()) != ignore_match {
+            return Ok(true);
         }
     }
-    return Ok(!found);
+    Ok(false)
 }
 
-/// Returns a vector with the file names matching the regular expression
+/// Opens an input for reading, the `-` input refers to the standard input.
 ///
 /// # Arguments
-/// * `inputs` - A vector of strings containing the path to the files
-/// * `re` - The `Regex` object with the regular expression to match
-/// * `ignore_match` - a bool that inverts the matching logic.  When `ignore_match`
-///    is true returns the files that do not include a match.
+/// * `input_file` - The path of the input
+/// * `output_formatter` - The OutputFormatter with the parsed command line flags
 ///
-/// If the standard input is searched, a pathname of "(standard input)" is written.
-fn find_matching_files(
-    inputs: &Vec<String>,
-    re: &Regex,
-    ignore_match: bool,
-) -> Result<Vec<String>, ErrCode> {
-    let mut matching_files: Vec<String> = Vec::new();
-
-    for input_file in inputs {
-        if input_file == "-" {
-            let stdin = io::stdin();
-            let reader = stdin.lock();
-            match find_match(reader, re, ignore_match) {
-                Ok(res) => {
-                    if res {
-                        matching_files.push(String::from("standard input"));
-                    }
-                }
-                Err(err) => return Err(err),
-            }
-        } else {
-            let f = File::open(input_file).unwrap();
-            let reader = BufReader::new(f);
-            match find_match(reader, re, ignore_match) {
-                Ok(res) => {
-                    if res {
-                        matching_files.push(String::from(input_file));
-                    }
-                }
-                Err(err) => return Err(err),
+/// With `--pre` the files are read from the output of the preprocessor
+/// command. With `--decompress` the inputs with a `.gz` extension or starting
+/// with the gzip magic bytes are decompressed on the fly, other inputs are
+/// read as they are. A directory is reported and not searched, `-r` replaces
+/// the directories by their files before.
+fn open_input(
+    input_file: &str,
+    output_formatter: &OutputFormatter,
+) -> Result<Box<dyn BufRead>, ErrCode> {
+    let mut reader: Box<dyn BufRead> = if input_file == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else if Path::new(input_file).is_dir() {
+        eprintln!("grep: {}: Is a directory", input_file);
+        return Err(ErrCode::ErrorInputIsADirectory);
+    } else if let Some(command) = &output_formatter.pre {
+        run_preprocessor(command, input_file)?
+    } else {
+        match File::open(input_file) {
+            Ok(f) => Box::new(BufReader::new(f)),
+            Err(err) => {
+                eprintln!("grep: {}: {}", input_file, describe_error(&err));
+                return Err(ErrCode::ErrorCannotOpenFileForReading);
             }
         }
+    };
+
+    if output_formatter.decompress && (input_file.ends_with(".gz") || is_gzip(&mut reader)) {
+        reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
     }
-    Ok(matching_files)
+    Ok(reader)
 }
 
-/// Returns the lines in the buffer that match the regular expression.
-///
-/// # Arguments
-/// * `reader` - A `BufRead` containing the text to match.
-/// * `re` - A RegEx object containing the regular expression
-/// * `ignore_match` - a bool that inverts the matching logic. When `ignore_match`
-///    is true returns the files that do not include a match.
-///
-/// # Returns
-/// Returns a vector of tupples,
-/// * `line number` : usize
-/// * `line text` : String
-fn match_lines<T: BufRead + Sized>(
-    reader: T,
-    re: &Regex,
-    ignore_match: bool,
-) -> Result<Vec<(usize, String)>, ErrCode> {
-    let mut matched_lines = Vec::new();
-    for (i, line_) in reader.lines().enumerate() {
-        let line = line_.unwrap();
-        if ignore_match && (!re.is_match(line.as_str())) {
-            matched_lines.push((i + 1, line));
-        } else if (!ignore_match) && re.is_match(line.as_str()) {
-            matched_lines.push((i + 1, line));
-        }
+/// Returns the description of an I/O error without the `(os error N)` suffix,
+/// like "No such file or directory".
+fn describe_error(err: &io::Error) -> String {
+    let description = err.to_string();
+    match description.find(" (os error") {
+        Some(end) => String::from(&description[..end]),
+        None => description,
     }
-    Ok(matched_lines)
 }
 
-fn main() {
-    let output_formatter = read_arguments(env::args_os());
-    let re = match Regex::new(output_formatter.pattern.as_str()) {
-        Ok(m) => m,
-        Err(_) => {
+/// Runs the preprocessor command with the path of the file as argument and
+/// returns a reader with its output.
+///
+/// A command that can not be run or that fails is reported as a file that can
+/// not be read.
+fn run_preprocessor(command: &str, input_file: &str) -> Result<Box<dyn BufRead>, ErrCode> {
+    match Command::new(command)
+        .arg(input_file)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+    {
+        Ok(output) if output.status.success() => Ok(Box::new(io::Cursor::new(output.stdout))),
+        Ok(output) => {
+            eprintln!(
+                "grep: {}: preprocessor `{}` failed: {}",
+                input_file, command, output.status
+            );
+            Err(ErrCode::ErrorCannotOpenFileForReading)
+        }
+        Err(err) => {
+            eprintln!("grep: {}: preprocessor `{}`: {}", input_file, command, err);
+            Err(ErrCode::ErrorCannotOpenFileForReading)
+        }
+    }
+}
+
+/// Returns true if the buffered content of the reader starts with the gzip
+/// magic bytes, the data is not consumed from the reader.
+fn is_gzip<T: BufRead>(reader: &mut T) -> bool {
+    match reader.fill_buf() {
+        Ok(buf) => buf.starts_with(&GZIP_MAGIC),
+        Err(_) => false,
+    }
+}
+
+/// Returns the list of files to search.
+///
+/// # Arguments
+/// * `inputs` - The input arguments given in the command line
+/// * `recursive` - a bool that enables the recursive search of directories
+/// * `sort_paths` - a bool that sorts by path the files found in each directory
+/// * `filter` - The names of the files found in the directories that are searched
+///
+/// Every argument is handled on its own: with `recursive` directory arguments
+/// are replaced by the regular files they contain, at any depth, while file
+/// arguments and the standard input are searched directly. The order of the
+/// files found in a directory depends on the file system unless `sort_paths`
+/// is set. The `filter` only applies to the files found in the directories.
+fn expand_inputs(
+    inputs: &[String],
+    recursive: bool,
+    sort_paths: bool,
+    filter: &NameFilter,
+) -> Vec<String> {
+    let mut files: Vec<String> = Vec::with_capacity(inputs.len());
+
+    for input in inputs {
+        if recursive && input != "-" && Path::new(input).is_dir() {
+            let mut found: Vec<String> = Vec::new();
+            walk_directory(Path::new(input), &mut found);
+            found.retain(|file| {
+                let name = Path::new(file).file_name().unwrap_or_default();
+                filter.accepts(&name.to_string_lossy())
+            });
+            if sort_paths {
+                found.sort();
+            }
+            files.append(&mut found);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    files
+}
+
+/// Appends the regular files found under `dir` to `files`.
+///
+/// Symbolic links to files are searched, symbolic links to directories are
+/// not followed.
+fn walk_directory(dir: &Path, files: &mut Vec<String>) {
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("grep: {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => pending.push(path),
+                Ok(file_type) if file_type.is_file() => files.push(path.display().to_string()),
+                Ok(file_type) if file_type.is_symlink() && path.is_file() => {
+                    files.push(path.display().to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns true if `name` matches the shell `glob`.
+///
+/// `*` matches any sequence of characters, `?` any single character and
+/// `[...]` any character of the set, which accepts ranges like `a-z` and is
+/// negated by a leading `!` or `^`. Any other character matches itself.
+fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_match_chars(&glob, &name)
+}
+
+fn glob_match_chars(glob: &[char], name: &[char]) -> bool {
+    match glob.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| glob_match_chars(&glob[1..], &name[i..])),
+        Some('?') => !name.is_empty() && glob_match_chars(&glob[1..], &name[1..]),
+        Some('[') if glob.contains(&']') => {
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            let negated = matches!(glob.get(1), Some('!') | Some('^'));
+            let start = if negated { 2 } else { 1 };
+            // a `]` right after the opening bracket is part of the set
+            let end = match glob[start + 1..].iter().position(|&g| g == ']') {
+                Some(i) => start + 1 + i,
+                None => return glob[0] == c && glob_match_chars(&glob[1..], &name[1..]),
+            };
+            let set = &glob[start..end];
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && glob_match_chars(&glob[end + 1..], &name[1..])
+        }
+        Some(&g) => name.first() == Some(&g) && glob_match_chars(&glob[1..], &name[1..]),
+    }
+}
+
+/// Returns the name used to report an input in the output.
+fn input_name(input_file: &str) -> String {
+    if input_file == "-" {
+        String::from("(standard input)")
+    } else {
+        String::from(input_file)
+    }
+}
+
+/// Returns true if the buffered content of the reader contains binary data.
+///
+/// Like GNU grep, only the first buffer is inspected for NUL bytes; the data is
+/// not consumed from the reader.
+fn is_binary<T: BufRead>(reader: &mut T) -> bool {
+    match reader.fill_buf() {
+        Ok(buf) => buf.contains(&0u8),
+        Err(_) => false,
+    }
+}
+
+/// Returns an iterator over the lines of the reader without their line
+/// terminator. The lines are decoded as UTF-8, invalid sequences are replaced
+/// with `U+FFFD` so the search goes on.
+///
+/// The lines end with `terminator`, a `\r` before a `\n` terminator is also
+/// removed.
+fn read_lines<T: BufRead>(reader: T, terminator: u8) -> impl Iterator<Item = String> {
+    read_lines_with_offsets(reader, terminator).map(|(_, line)| line)
+}
+
+/// Returns an iterator over the lines of the reader like `read_lines`, every
+/// line is paired with the offset of its first byte in the input.
+///
+/// The offsets count the bytes read, the terminators included, so they are
+/// not affected by the decoding of the lines.
+fn read_lines_with_offsets<T: BufRead>(
+    reader: T,
+    terminator: u8,
+) -> impl Iterator<Item = (u64, String)> {
+    read_raw_lines_with_offsets(reader, terminator)
+        .map(|(offset, line)| (offset, String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Returns an iterator over the lines of the reader like
+/// `read_lines_with_offsets`, the lines are not decoded.
+fn read_raw_lines_with_offsets<T: BufRead>(
+    mut reader: T,
+    terminator: u8,
+) -> impl Iterator<Item = (u64, Vec<u8>)> {
+    let mut offset = 0u64;
+    std::iter::from_fn(move || {
+        let mut buf: Vec<u8> = Vec::new();
+        match reader.read_until(terminator, &mut buf) {
+            Ok(0) | Err(_) => None,
+            Ok(n) => {
+                let start = offset;
+                offset += n as u64;
+                let line = if terminator == b'\n' {
+                    split_lines_with_terminators(&buf)
+                        .next()
+                        .map_or(&buf[..], |(content, _)| content)
+                } else if buf.last() == Some(&terminator) {
+                    &buf[..n - 1]
+                } else {
+                    &buf[..]
+                };
+                Some((start, line.to_vec()))
+            }
+        }
+    })
+}
+
+/// Returns the offset in the raw bytes of a line of the byte at `offset` in
+/// the line decoded by `read_lines`. Every invalid sequence of the raw line is
+/// a `U+FFFD` of three bytes in the decoded line.
+fn raw_offset(raw_line: &[u8], offset: usize) -> usize {
+    let mut decoded = 0;
+    let mut raw = 0;
+    for chunk in raw_line.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if offset < decoded + valid {
+            return raw + offset - decoded;
+        }
+        decoded += valid;
+        raw += valid;
+        if !chunk.invalid().is_empty() {
+            if offset < decoded + char::REPLACEMENT_CHARACTER.len_utf8() {
+                return raw;
+            }
+            decoded += char::REPLACEMENT_CHARACTER.len_utf8();
+            raw += chunk.invalid().len();
+        }
+    }
+    raw + offset.saturating_sub(decoded)
+}
+
+/// Returns the whole contents of the reader decoded as UTF-8, invalid
+/// sequences are replaced with `U+FFFD` like in `read_lines`. A read error
+/// ends the contents.
+fn read_text<T: BufRead>(mut reader: T) -> String {
+    let mut buf: Vec<u8> = Vec::new();
+    let _ = reader.read_to_end(&mut buf);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// A reader that checks that the data read is valid UTF-8.
+///
+/// The result is shared through the `invalid_utf8` cell, so it can be checked
+/// after the reader is consumed by the search functions. The number of bytes
+/// read is shared in the same way through `bytes_read`.
+struct Utf8Tracker<T: BufRead> {
+    inner: T,
+    /// The bytes of a character split between two buffers
+    pending: Vec<u8>,
+    invalid_utf8: Rc<Cell<bool>>,
+    bytes_read: Rc<Cell<u64>>,
+}
+
+impl<T: BufRead> Utf8Tracker<T> {
+    fn new(inner: T) -> Utf8Tracker<T> {
+        Utf8Tracker {
+            inner,
+            pending: Vec::new(),
+            invalid_utf8: Rc::new(Cell::new(false)),
+            bytes_read: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Checks the bytes that follow the ones checked before.
+    fn check(&mut self, bytes: &[u8]) {
+        self.bytes_read
+            .set(self.bytes_read.get() + bytes.len() as u64);
+        if self.invalid_utf8.get() || bytes.is_empty() {
+            return;
+        }
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(bytes);
+        if let Err(err) = std::str::from_utf8(&data) {
+            match err.error_len() {
+                Some(_) => self.invalid_utf8.set(true),
+                None => self.pending = data[err.valid_up_to()..].to_vec(),
+            }
+        }
+    }
+}
+
+impl<T: BufRead> Read for Utf8Tracker<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.check(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: BufRead> BufRead for Utf8Tracker<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The buffer of the inner reader still holds the consumed bytes
+        let consumed = match self.inner.fill_buf() {
+            Ok(buf) => buf[..amt].to_vec(),
+            Err(_) => Vec::new(),
+        };
+        self.check(&consumed);
+        self.inner.consume(amt);
+    }
+}
+
+/// Returns a vector with the file names matching the regular expression
+///
+/// # Arguments
+/// * `inputs` - A vector of strings containing the path to the files
+/// * `re` - The `Regex` object with the regular expression to match
+/// * `output_formatter` - The OutputFormatter with the parsed command line flags
+///
+/// With `-v` the files that include a line that does not match are returned,
+/// like GNU grep. With `-L` the files are listed the other way around, the
+/// files without a selected line are returned. With `--binary-files=without-match` binary files are
+/// never listed.
+///
+/// If the standard input is searched, a pathname of "(standard input)" is written.
+///
+/// The inputs that can not be opened are reported and skipped, `failed` is set
+/// so the caller exits with an error after searching the other inputs.
+fn find_matching_files(
+    inputs: &Vec<String>,
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+    failed: &mut bool,
+) -> Result<Vec<String>, ErrCode> {
+    let mut matching_files: Vec<String> = Vec::new();
+
+    for input_file in inputs {
+        let mut reader = match open_input(input_file, output_formatter) {
+            Ok(reader) => reader,
+            Err(_) => {
+                *failed = true;
+                continue;
+            }
+        };
+        if output_formatter.binary_files == BinaryFiles::WithoutMatch
+            && !output_formatter.null_data
+            && is_binary(&mut reader)
+        {
+            continue;
+        }
+        let found = if output_formatter.multiline {
+            re.is_match(read_text(reader).as_bytes())
+        } else {
+            find_match(
+                reader,
+                re,
+                output_formatter.ignore_match,
+                output_formatter.terminator(),
+            )?
+        };
+        if found != output_formatter.files_without_match {
+            matching_files.push(input_name(input_file));
+        }
+    }
+    Ok(matching_files)
+}
+
+/// Returns true if any input has a selected line.
+///
+/// The search stops at the first selected line, the rest of the input and the
+/// other inputs are not read. The inputs that can not be opened are reported
+/// and skipped, `failed` is set. Like in `find_matching_files`, the binary
+/// inputs are skipped with `--binary-files=without-match`.
+fn has_selected_line(
+    inputs: &[String],
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+    failed: &mut bool,
+) -> Result<bool, ErrCode> {
+    for input_file in inputs {
+        let mut reader = match open_input(input_file, output_formatter) {
+            Ok(reader) => reader,
+            Err(_) => {
+                *failed = true;
+                continue;
+            }
+        };
+        if output_formatter.binary_files == BinaryFiles::WithoutMatch
+            && !output_formatter.null_data
+            && is_binary(&mut reader)
+        {
+            continue;
+        }
+        if output_formatter.multiline {
+            if re.is_match(read_text(reader).as_bytes()) {
+                return Ok(true);
+            }
+            continue;
+        }
+        let mut lines = match_lines(
+            reader,
+            re,
+            output_formatter.ignore_match,
+            Some(1),
+            output_formatter.terminator(),
+        );
+        if lines.next().is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns true if the line read by `read_raw_lines_with_offsets` matches
+/// the regular expression once decoded like the lines of `read_lines`.
+fn is_line_match(re: &Regex, line: &[u8]) -> bool {
+    re.is_match(String::from_utf8_lossy(line).as_bytes())
+}
+
+/// Returns an iterator over the lines in the buffer that match the regular
+/// expression.
+///
+/// # Arguments
+/// * `reader` - A `BufRead` containing the text to match.
+/// * `re` - A RegEx object containing the regular expression
+/// * `ignore_match` - a bool that inverts the matching logic. When `ignore_match`
+///   is true returns the files that do not include a match.
+/// * `max_count` - stop reading after this number of matching lines, if set.
+/// * `terminator` - The byte that ends the lines, `\n` or NUL with `-z`
+///
+/// The lines are read as the iterator advances, only the current line is held
+/// in memory. The reading stops at the last matching line allowed by
+/// `max_count`.
+///
+/// # Returns
+/// Returns an iterator of tupples,
+/// * `line number` : usize
+/// * `line text` : String
+fn match_lines<'a, T: BufRead + 'a>(
+    reader: T,
+    re: &'a Regex,
+    ignore_match: bool,
+    max_count: Option<usize>,
+    terminator: u8,
+) -> impl Iterator<Item = (usize, String)> + 'a {
+    match_lines_with_offsets(reader, re, ignore_match, max_count, terminator)
+        .map(|(n, _, line)| (n, String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Returns an iterator over the matching lines like `match_lines`, the tupples
+/// include the offset of the line in the input after the line number. The
+/// lines are matched decoded but returned as they were read, so the offsets
+/// of the matches can be mapped to the input with `raw_offset`.
+fn match_lines_with_offsets<'a, T: BufRead + 'a>(
+    reader: T,
+    re: &'a Regex,
+    ignore_match: bool,
+    max_count: Option<usize>,
+    terminator: u8,
+) -> impl Iterator<Item = (usize, u64, Vec<u8>)> + 'a {
+    read_raw_lines_with_offsets(reader, terminator)
+        .enumerate()
+        .filter(move |(_, (_, line))| ignore_match != is_line_match(re, line))
+        .map(|(i, (offset, line))| (i + 1, offset, line))
+        .take(max_count.unwrap_or(usize::MAX))
+}
+
+/// Returns an iterator over the matching lines in the buffer and the lines of
+/// context around them.
+///
+/// # Arguments
+/// * `reader` - A `BufRead` containing the text to match.
+/// * `re` - A RegEx object containing the regular expression
+/// * `output_formatter` - The OutputFormatter with the `-v`, `-m`, `-A` and `-B` flags
+///
+/// The context of consecutive matches is merged, every line is returned once.
+/// After the `-m` matching line only its trailing context is returned. Only
+/// the `-B` lines that precede the next match are held in memory.
+///
+/// # Returns
+/// Returns an iterator of tupples,
+/// * `line number` : usize
+/// * `line offset` : u64, the offset of the line in the input
+/// * `line bytes` : Vec<u8>, the line as read, like `match_lines_with_offsets`
+/// * `is match` : bool, false for the context lines
+fn match_lines_with_context<'a, T: BufRead + 'a>(
+    reader: T,
+    re: &'a Regex,
+    output_formatter: &'a OutputFormatter,
+) -> impl Iterator<Item = (usize, u64, Vec<u8>, bool)> + 'a {
+    let mut lines = read_raw_lines_with_offsets(reader, output_formatter.terminator()).enumerate();
+    let mut pending: VecDeque<(usize, u64, Vec<u8>, bool)> = VecDeque::new();
+    let mut before: VecDeque<(usize, u64, Vec<u8>)> = VecDeque::new();
+    let mut after_remaining = 0;
+    let mut count = 0;
+    std::iter::from_fn(move || loop {
+        if let Some(line) = pending.pop_front() {
+            return Some(line);
+        }
+        let is_done = Some(count) == output_formatter.max_count;
+        if is_done && after_remaining == 0 {
+            return None;
+        }
+        let (i, (offset, line)) = lines.next()?;
+        if !is_done && output_formatter.ignore_match != is_line_match(re, &line) {
+            count += 1;
+            pending.extend(
+                before
+                    .drain(..)
+                    .map(|(n, offset, line)| (n, offset, line, false)),
+            );
+            pending.push_back((i + 1, offset, line, true));
+            after_remaining = output_formatter.after_context;
+        } else if after_remaining > 0 {
+            after_remaining -= 1;
+            return Some((i + 1, offset, line, false));
+        } else if output_formatter.before_context > 0 {
+            if before.len() == output_formatter.before_context {
+                before.pop_front();
+            }
+            before.push_back((i + 1, offset, line));
+        }
+    })
+}
+
+/// Adds the patterns of the `-f` files to the `-e` patterns.
+///
+/// Every line of a file is a pattern, an empty line matches every line. The
+/// standard input can only be read for the patterns when the inputs are not
+/// read from it.
+fn read_pattern_files(output_formatter: &mut OutputFormatter) -> Result<(), ErrCode> {
+    for file in &output_formatter.pattern_files {
+        if file == "-" && output_formatter.inputs.iter().any(|input| input == "-") {
+            eprintln!(
+                "grep: the patterns and the input can not both be read from the standard input"
+            );
+            return Err(ErrCode::ErrorArgumentParsing);
+        }
+        let contents = read_all(file)?;
+        output_formatter
+            .patterns
+            .extend(read_lines(contents.as_slice(), b'\n'));
+    }
+    Ok(())
+}
+
+/// Returns true if the pattern does not contain regular expression
+/// metacharacters, so it only matches itself.
+fn is_literal(pattern: &str) -> bool {
+    regex::escape(pattern) == pattern
+}
+
+/// Returns the engine used to match the pattern, resolving `Engine::Auto`.
+fn select_engine(engine: Engine, pattern: &str) -> Engine {
+    match engine {
+        Engine::Auto if is_literal(pattern) => Engine::Literal,
+        Engine::Auto => Engine::Regex,
+        engine => engine,
+    }
+}
+
+/// Compiles the pattern in the OutputFormatter into a `Regex`.
+///
+/// This is the only place where the pattern is compiled; the resulting object
+/// is built once in `main` and passed by reference to every search function,
+/// so searching several files never recompiles the expression.
+///
+/// With the literal engine the metacharacters of the pattern are escaped; the
+/// regex crate searches literal expressions with a substring search instead
+/// of running the regular expression automaton.
+///
+/// Several patterns are combined in a single expression as the alternatives
+/// `(?:p1)|(?:p2)|...`, the engine is selected for every pattern.
+///
+/// With `-w` the pattern is grouped and surrounded by word boundaries, so the
+/// boundaries apply to all the alternatives of the pattern. With `-x` it is
+/// anchored to the start and end of the line instead, `-x` takes precedence.
+/// The lines are matched without their terminator, `\r` included.
+///
+/// With `-U` `^` and `$` also match at the start and end of every line of the
+/// input, and with `--dotall` `.` matches the line terminators.
+///
+/// The lines are matched as bytes. With `--no-unicode` the expression is
+/// compiled without Unicode support: `\w`, `\d`, `\s`, `\b` and the case
+/// insensitive matching only know about ASCII, so a non ASCII letter is not a
+/// word character, and `.` or `[^a]` match a single byte, even one of a
+/// multibyte character.
+fn build_regex(output_formatter: &OutputFormatter) -> Result<Regex, ErrCode> {
+    #[cfg(test)]
+    REGEX_BUILDS.with(|builds| builds.set(builds.get() + 1));
+    let patterns: Vec<String> = output_formatter
+        .patterns
+        .iter()
+        .map(
+            |pattern| match select_engine(output_formatter.engine, pattern) {
+                Engine::Literal => regex::escape(pattern),
+                _ => pattern.clone(),
+            },
+        )
+        .collect();
+    // No pattern matches nothing, with an empty class that is valid with and
+    // without Unicode, an empty pattern matches everything
+    let mut pattern = if patterns.is_empty() {
+        String::from(r"[a&&b]")
+    } else if patterns.len() == 1 {
+        patterns[0].clone()
+    } else {
+        patterns
+            .iter()
+            .map(|pattern| format!("(?:{})", pattern))
+            .collect::<Vec<String>>()
+            .join("|")
+    };
+    if output_formatter.line_match {
+        pattern = format!(r"^(?:{})$", pattern);
+    } else if output_formatter.word_match {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+    match RegexBuilder::new(pattern.as_str())
+        .case_insensitive(output_formatter.ignore_case)
+        .unicode(!output_formatter.no_unicode)
+        .multi_line(output_formatter.multiline)
+        .dot_matches_new_line(output_formatter.dotall)
+        .build()
+    {
+        Ok(re) => Ok(re),
+        Err(_) => {
             eprintln!(
                 "Error: {} is not a valid regular expression",
-                output_formatter.pattern.as_str()
+                output_formatter.patterns.join(", ")
             );
-            process::exit(ErrCode::InvalidRegularExpression as i32);
+            Err(ErrCode::InvalidRegularExpression)
+        }
+    }
+}
+
+/// Returns the line with the matches of the regular expression wrapped in
+/// ANSI color escape sequences.
+fn highlight_matches(line: &str, re: &Regex) -> String {
+    let line = line.as_bytes();
+    let mut highlighted = Vec::with_capacity(line.len());
+    let mut last = 0;
+    for m in re.find_iter(line).filter(|m| !m.as_bytes().is_empty()) {
+        highlighted.extend_from_slice(&line[last..m.start()]);
+        highlighted.extend_from_slice(COLOR_MATCH.as_bytes());
+        highlighted.extend_from_slice(m.as_bytes());
+        highlighted.extend_from_slice(COLOR_RESET.as_bytes());
+        last = m.end();
+    }
+    highlighted.extend_from_slice(&line[last..]);
+    String::from_utf8_lossy(&highlighted).into_owned()
+}
+
+/// Returns the prefix of an output line with the file name, line number and
+/// byte offset when they are requested.
+///
+/// # Arguments
+/// * `output_formatter` - The OutputFormatter with the parsed command line flags
+/// * `current_file` - The name of the input printed with `-H`
+/// * `line_number` - The number of the line printed with `-n`
+/// * `offset` - The offset of the line, or of the match with `-o`, printed with `-b`
+/// * `is_match` - a bool, false for the context lines printed with `--passthru`
+///
+/// By default the file name is followed by `:` and the line number and the
+/// offset by `: ` in matching lines, and by `-` and `- ` in context lines.
+/// `--field-separator` replaces them all.
+fn line_prefix(
+    output_formatter: &OutputFormatter,
+    current_file: &str,
+    line_number: usize,
+    offset: u64,
+    is_match: bool,
+) -> String {
+    let (file_separator, number_separator) = match &output_formatter.field_separator {
+        Some(separator) => (separator.as_str(), separator.as_str()),
+        None if is_match => (":", ": "),
+        None => ("-", "- "),
+    };
+    format!(
+        "{}{}{}",
+        if output_formatter.with_file_name && !output_formatter.heading {
+            format!("{}{}", current_file, file_separator)
+        } else {
+            String::new()
+        },
+        if output_formatter.has_line_numbers {
+            format!("{}{}", line_number, number_separator)
+        } else {
+            String::new()
+        },
+        if output_formatter.byte_offset {
+            format!("{}{}", offset, number_separator)
+        } else {
+            String::new()
+        }
+    )
+}
+
+/// Returns the notice printed instead of a line longer than `max_columns`
+/// characters, or None if the line is printed.
+fn omit_long_line(line: &str, re: &Regex, max_columns: Option<usize>) -> Option<String> {
+    match max_columns {
+        Some(max_columns) if line.chars().count() > max_columns => {
+            match re.find_iter(line.as_bytes()).count() {
+                0 => Some(String::from("[Omitted long context line]")),
+                matches => Some(format!("[Omitted long line with {} matches]", matches)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the template expanded with the capture groups of every match in
+/// the line, `$0` is the whole match and `$1`, `$2`... the groups.
+fn expand_matches(line: &str, re: &Regex, template: &str) -> Vec<String> {
+    re.captures_iter(line.as_bytes())
+        .map(|caps| {
+            let mut expanded = Vec::new();
+            caps.expand(template.as_bytes(), &mut expanded);
+            String::from_utf8_lossy(&expanded).into_owned()
+        })
+        .collect()
+}
+
+/// Returns the texts written for the matches of a line with `-o` or
+/// `--replace`, the empty matches are not written. Every text is paired with
+/// the offset of its match in the line.
+///
+/// With `--nth` only the text of that match is returned, none if the line has
+/// fewer matches.
+fn match_texts(line: &str, re: &Regex, output_formatter: &OutputFormatter) -> Vec<(usize, String)> {
+    let mut texts = all_match_texts(line, re, output_formatter);
+    match output_formatter.nth {
+        Some(n) => {
+            let index = if n > 0 {
+                (n - 1) as usize
+            } else {
+                match texts.len().checked_sub(n.unsigned_abs() as usize) {
+                    Some(index) => index,
+                    None => return Vec::new(),
+                }
+            };
+            if index < texts.len() {
+                vec![texts.swap_remove(index)]
+            } else {
+                Vec::new()
+            }
+        }
+        None => texts,
+    }
+}
+
+/// Returns the texts of every match of a line for `match_texts`.
+fn all_match_texts(
+    line: &str,
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+) -> Vec<(usize, String)> {
+    if let Some(template) = &output_formatter.replace {
+        return re
+            .find_iter(line.as_bytes())
+            .zip(expand_matches(line, re, template))
+            .filter(|(m, _)| !m.as_bytes().is_empty())
+            .map(|(m, text)| (m.start(), text))
+            .collect();
+    }
+    re.find_iter(line.as_bytes())
+        .filter(|m| !m.as_bytes().is_empty())
+        .map(|m| {
+            let text = String::from_utf8_lossy(m.as_bytes());
+            if output_formatter.color {
+                (m.start(), format!("{}{}{}", COLOR_MATCH, text, COLOR_RESET))
+            } else {
+                (m.start(), text.into_owned())
+            }
+        })
+        .collect()
+}
+
+/// Writes every line of the reader to `handle`, highlighting the matches when
+/// color is enabled.
+///
+/// With `-v` the selected lines are the lines without a match: they are
+/// counted and prefixed like matching lines, the other lines like context
+/// lines. Nothing is highlighted, the selected lines have no match to
+/// highlight, like the lines printed by `-v` without `--passthru`.
+///
+/// # Returns
+/// The number of selected lines found.
+fn print_passthru<W: Write, T: BufRead>(
+    handle: &mut W,
+    reader: T,
+    current_file: &str,
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+) -> Result<usize, ErrCode> {
+    let mut count = 0;
+    for (i, (offset, line)) in
+        read_lines_with_offsets(reader, output_formatter.terminator()).enumerate()
+    {
+        let is_match = re.is_match(line.as_bytes());
+        let is_selected = is_match != output_formatter.ignore_match;
+        if is_selected {
+            count += 1;
+        }
+        let text = if let Some(notice) = omit_long_line(&line, re, output_formatter.max_columns) {
+            notice
+        } else if is_match && output_formatter.color && !output_formatter.ignore_match {
+            highlight_matches(&line, re)
+        } else {
+            line
+        };
+        write!(
+            handle,
+            "{}{}{}",
+            line_prefix(output_formatter, current_file, i + 1, offset, is_selected),
+            text,
+            output_formatter.terminator() as char
+        )
+        .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+    }
+    Ok(count)
+}
+
+/// Writes the lines of the reader spanned by the matches of the regular
+/// expression to `handle`, the expression is matched against the whole input.
+///
+/// Every line is written once, even if several matches span it. With `-o` or
+/// `--replace` every match is written instead, prefixed with the number of
+/// the line where it starts; a match can contain line terminators. `-m`
+/// limits the number of matches.
+///
+/// # Returns
+/// The number of lines spanned by the matches.
+fn print_multiline_matches<W: Write, T: BufRead>(
+    handle: &mut W,
+    reader: T,
+    current_file: &str,
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+) -> Result<usize, ErrCode> {
+    let text = read_text(reader);
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|&start| start < text.len())
+        .collect();
+    // The index of the line that contains the byte at `offset`
+    let line_of = |offset: usize| {
+        line_starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1)
+    };
+
+    let max_count = output_formatter.max_count.unwrap_or(usize::MAX);
+    let mut selected = vec![false; line_starts.len()];
+    for m in re.find_iter(text.as_bytes()).take(max_count) {
+        // An empty match selects the line where it is found
+        let last = line_of(m.end().max(m.start() + 1) - 1);
+        for is_selected in selected.iter_mut().take(last + 1).skip(line_of(m.start())) {
+            *is_selected = true;
+        }
+    }
+    let count = selected.iter().filter(|&&is_selected| is_selected).count();
+    if output_formatter.only_line_count {
+        return Ok(count);
+    }
+
+    if output_formatter.only_matching || output_formatter.replace.is_some() {
+        let texts = match_texts(&text, re, output_formatter);
+        for (start, match_text) in texts.into_iter().take(max_count) {
+            let line = line_of(start);
+            writeln!(
+                handle,
+                "{}{}",
+                line_prefix(output_formatter, current_file, line + 1, start as u64, true),
+                match_text
+            )
+            .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+        }
+        return Ok(count);
+    }
+
+    for (i, &start) in line_starts.iter().enumerate() {
+        if !selected[i] {
+            continue;
+        }
+        let end = line_starts.get(i + 1).copied().unwrap_or(text.len());
+        let line = text[start..end].trim_end_matches('\n');
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let line = match omit_long_line(line, re, output_formatter.max_columns) {
+            Some(notice) => notice,
+            None => String::from(line),
+        };
+        writeln!(
+            handle,
+            "{}{}",
+            line_prefix(output_formatter, current_file, i + 1, start as u64, true),
+            line
+        )
+        .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+    }
+    Ok(count)
+}
+
+/// Writes the lines of the reader matching the regular expression to `handle`.
+///
+/// # Arguments
+/// * `handle` - A `Write` where the output is written
+/// * `reader` - A `BufRead` containing the text to match.
+/// * `current_file` - The name of the input printed with `-H`
+/// * `re` - A RegEx object containing the regular expression
+/// * `output_formatter` - The OutputFormatter with the parsed command line flags
+///
+/// When the input contains binary data, and binary files are not handled as
+/// text, a single "Binary file ... matches" line is written instead of the lines.
+/// A NUL byte found after the first buffer, in a line about to be written,
+/// writes the same line and ends the search of the input.
+///
+/// With `-o` every match, or with `--replace` the expanded template of every
+/// match, is written in its own line instead of the matching line. With `-z`
+/// the lines, and every match of `-o`, end with a NUL byte.
+///
+/// # Returns
+/// The number of matching lines found.
+fn print_matches<W: Write, T: BufRead>(
+    handle: &mut W,
+    mut reader: T,
+    current_file: &str,
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+) -> Result<usize, ErrCode> {
+    // The NUL bytes of `-z` records do not make the input binary
+    let binary = output_formatter.binary_files != BinaryFiles::Text
+        && !output_formatter.null_data
+        && is_binary(&mut reader);
+    if binary && output_formatter.binary_files == BinaryFiles::WithoutMatch {
+        return Ok(0);
+    }
+
+    if output_formatter.passthru && !binary {
+        return print_passthru(handle, reader, current_file, re, output_formatter);
+    }
+
+    if output_formatter.multiline && !binary {
+        return print_multiline_matches(handle, reader, current_file, re, output_formatter);
+    }
+
+    // The lines are written as they are found
+    let has_context = output_formatter.before_context > 0 || output_formatter.after_context > 0;
+    let lines: Box<dyn Iterator<Item = (usize, u64, Vec<u8>, bool)>> =
+        if has_context && !output_formatter.only_line_count && !binary {
+            Box::new(match_lines_with_context(reader, re, output_formatter))
+        } else {
+            Box::new(
+                match_lines_with_offsets(
+                    reader,
+                    re,
+                    output_formatter.ignore_match,
+                    output_formatter.max_count,
+                    output_formatter.terminator(),
+                )
+                .map(|(n, offset, line)| (n, offset, line, true)),
+            )
+        };
+    if output_formatter.only_line_count || binary {
+        let count = lines.filter(|line| line.3).count();
+        // With -c only the count is written, binary input or not
+        if binary && count > 0 && !output_formatter.only_line_count {
+            writeln!(handle, "Binary file {} matches", current_file)
+                .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+        }
+        return Ok(count);
+    }
+
+    let mut count = 0;
+    let mut previous_line: Option<usize> = None;
+    let detect_binary =
+        output_formatter.binary_files == BinaryFiles::Binary && !output_formatter.null_data;
+    for (line_number, offset, raw_line, is_match) in lines {
+        let line = String::from_utf8_lossy(&raw_line).into_owned();
+        if is_match {
+            count += 1;
+        }
+        if detect_binary && line.contains('\0') {
+            writeln!(handle, "Binary file {} matches", current_file)
+                .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+            return Ok(count);
+        }
+        // The groups of lines that are not contiguous are separated by `--`
+        if has_context && previous_line.is_some_and(|n| n + 1 != line_number) {
+            writeln!(handle, "--").map_err(|_| ErrCode::ErrorWriteToStdout)?;
+        }
+        previous_line = Some(line_number);
+
+        if output_formatter.only_matching || output_formatter.replace.is_some() {
+            if !is_match {
+                continue;
+            }
+            for (start, text) in match_texts(&line, re, output_formatter) {
+                write!(
+                    handle,
+                    "{}{}{}",
+                    line_prefix(
+                        output_formatter,
+                        current_file,
+                        line_number,
+                        offset + raw_offset(&raw_line, start) as u64,
+                        true
+                    ),
+                    text,
+                    output_formatter.terminator() as char
+                )
+                .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+            }
+            continue;
+        }
+        let text = if let Some(notice) = omit_long_line(&line, re, output_formatter.max_columns) {
+            notice
+        } else if is_match && output_formatter.color && !output_formatter.ignore_match {
+            highlight_matches(&line, re)
+        } else {
+            line
+        };
+        write!(
+            handle,
+            "{}{}{}",
+            line_prefix(
+                output_formatter,
+                current_file,
+                line_number,
+                offset,
+                is_match
+            ),
+            text,
+            output_formatter.terminator() as char
+        )
+        .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+    }
+    Ok(count)
+}
+
+fn main() {
+    let output_formatter = read_arguments(env::args_os());
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    process::exit(run(&mut handle, output_formatter));
+}
+
+/// Searches the inputs and writes the output to `handle`.
+///
+/// # Returns
+/// The exit status of grep, see `exit_code`. Every error, like an invalid
+/// pattern, returns the status 2.
+fn run<W: Write>(handle: &mut W, mut output_formatter: OutputFormatter) -> i32 {
+    if read_pattern_files(&mut output_formatter).is_err() {
+        return exit_code(false, true);
+    }
+    let compile_start = Instant::now();
+    let re = match build_regex(&output_formatter) {
+        Ok(re) => re,
+        Err(_) => return exit_code(false, true),
+    };
+    let compile_time = compile_start.elapsed();
+
+    let inputs = expand_inputs(
+        &output_formatter.inputs,
+        output_formatter.recursive,
+        output_formatter.sort_paths,
+        &output_formatter.name_filter,
+    );
+
+    // An input that can not be opened is skipped, grep exits with an error
+    // after searching the others
+    let mut failed = false;
+
+    // With -q grep exits at the first selected line, even if an input failed
+    if output_formatter.quiet && !output_formatter.count_as_exit {
+        return match has_selected_line(&inputs, &re, &output_formatter, &mut failed) {
+            Ok(found) => quiet_exit_code(found, failed),
+            Err(_) => exit_code(false, true),
+        };
+    }
+
+    // Fast implementation for finding files that match the expression
+    if output_formatter.only_file_names || output_formatter.files_without_match {
+        let matched_files = match find_matching_files(&inputs, &re, &output_formatter, &mut failed)
+        {
+            Ok(matched_files) => matched_files,
+            Err(_) => {
+                eprintln!("Error");
+                return exit_code(false, true);
+            }
+        };
+        for file_name in &matched_files {
+            if writeln!(handle, "{}", file_name).is_err() {
+                return exit_code(false, true);
+            }
+        }
+        if output_formatter.count_as_exit && !failed {
+            return count_exit_code(matched_files.len());
+        }
+        return exit_code(!matched_files.is_empty(), failed);
+    }
+
+    // More complex implementation for finding lines that match the expression,
+    // with -q and --count-as-exit the lines are only counted
+    let scan_start = Instant::now();
+    let summary = if output_formatter.quiet {
+        search_inputs(&mut io::sink(), &inputs, &re, &output_formatter)
+    } else {
+        search_inputs(handle, &inputs, &re, &output_formatter)
+    };
+    let summary = match summary {
+        Ok(summary) => summary,
+        Err(_) => {
+            eprintln!("Error");
+            return exit_code(false, true);
+        }
+    };
+
+    if output_formatter.report_encoding_issues {
+        for line in format_encoding_report(&summary.encoding_issues) {
+            eprintln!("{}", line);
+        }
+    }
+
+    if output_formatter.stats {
+        for line in format_stats(compile_time, scan_start.elapsed(), summary.bytes_scanned) {
+            eprintln!("{}", line);
+        }
+    }
+
+    let failed = failed || summary.failed;
+    if output_formatter.count_as_exit && !failed {
+        return count_exit_code(summary.line_count);
+    }
+    exit_code(summary.line_count > 0, failed)
+}
+
+/// Returns the exit status of grep: 0 when a line is selected, 1 when no line
+/// is selected and 2 on an error, like an input that could not be read, even
+/// if other inputs have selected lines.
+fn exit_code(found: bool, failed: bool) -> i32 {
+    if failed {
+        ErrCode::ErrorCannotOpenFileForReading as i32
+    } else if found {
+        0
+    } else {
+        1
+    }
+}
+
+/// Returns the exit status of grep with `-q`: 0 when a line is selected, even
+/// if an input could not be read, otherwise like `exit_code`.
+fn quiet_exit_code(found: bool, failed: bool) -> i32 {
+    if found {
+        0
+    } else {
+        exit_code(found, failed)
+    }
+}
+
+/// The outcome of searching the inputs with `search_inputs`.
+struct SearchSummary {
+    /// Number of selected lines in all the inputs
+    line_count: usize,
+    /// The inputs that are not valid UTF-8
+    encoding_issues: Vec<String>,
+    /// True if an input could not be opened
+    failed: bool,
+    /// Number of bytes read from all the inputs
+    bytes_scanned: u64,
+}
+
+/// Writes the matching lines of every input to `handle`, or the count of
+/// matching lines of every input with `-c`.
+///
+/// With `--heading` the output of every input is buffered, and only written
+/// after the name of the input when it is not empty.
+///
+/// The inputs that can not be opened are reported and skipped.
+fn search_inputs<W: Write>(
+    handle: &mut W,
+    inputs: &[String],
+    re: &Regex,
+    output_formatter: &OutputFormatter,
+) -> Result<SearchSummary, ErrCode> {
+    let mut summary = SearchSummary {
+        line_count: 0,
+        encoding_issues: Vec::new(),
+        failed: false,
+        bytes_scanned: 0,
+    };
+    let mut is_first_heading = true;
+    for input_file in inputs {
+        let reader = match open_input(input_file, output_formatter) {
+            Ok(reader) => Utf8Tracker::new(reader),
+            Err(_) => {
+                summary.failed = true;
+                continue;
+            }
+        };
+        let invalid_utf8 = Rc::clone(&reader.invalid_utf8);
+        let bytes_read = Rc::clone(&reader.bytes_read);
+        let current_file = input_name(input_file);
+        let count = if output_formatter.heading {
+            let mut output: Vec<u8> = Vec::new();
+            let count = print_matches(&mut output, reader, &current_file, re, output_formatter)?;
+            if !output.is_empty() {
+                if !is_first_heading {
+                    writeln!(handle).map_err(|_| ErrCode::ErrorWriteToStdout)?;
+                }
+                is_first_heading = false;
+                writeln!(handle, "{}", current_file).map_err(|_| ErrCode::ErrorWriteToStdout)?;
+                handle
+                    .write_all(&output)
+                    .map_err(|_| ErrCode::ErrorWriteToStdout)?;
+            }
+            count
+        } else {
+            print_matches(handle, reader, &current_file, re, output_formatter)?
+        };
+        summary.line_count += count;
+        if output_formatter.only_line_count {
+            let line = format_count(output_formatter, &current_file, count);
+            writeln!(handle, "{}", line).map_err(|_| ErrCode::ErrorWriteToStdout)?;
+        }
+        if invalid_utf8.get() {
+            summary.encoding_issues.push(current_file);
+        }
+        summary.bytes_scanned += bytes_read.get();
+    }
+    Ok(summary)
+}
+
+/// Returns the `-c` line of an input, the count is prefixed with the name of
+/// the input with `-H` or when several inputs are searched.
+fn format_count(output_formatter: &OutputFormatter, current_file: &str, count: usize) -> String {
+    if output_formatter.with_file_name {
+        let separator = output_formatter.field_separator.as_deref().unwrap_or(":");
+        format!("{}{}{}", current_file, separator, count)
+    } else {
+        count.to_string()
+    }
+}
+
+/// Returns the exit code of `--count-as-exit`, the number of matches capped
+/// at 255, the largest exit status seen by the shell.
+fn count_exit_code(count: usize) -> i32 {
+    cmp::min(count, 255) as i32
+}
+
+/// Returns the lines of the `--stats` report with the time spent compiling the
+/// pattern and scanning the inputs, the bytes scanned and the throughput.
+fn format_stats(compile_time: Duration, scan_time: Duration, bytes_scanned: u64) -> Vec<String> {
+    let milliseconds = |time: Duration| time.as_secs_f64() * 1000.0;
+    let throughput = if scan_time.as_secs_f64() > 0.0 {
+        format!(
+            "{:.2} MB/s",
+            bytes_scanned as f64 / scan_time.as_secs_f64() / 1_000_000.0
+        )
+    } else {
+        String::from("-")
+    };
+    vec![
+        format!("grep: compile: {:.3} ms", milliseconds(compile_time)),
+        format!("grep: scan: {:.3} ms", milliseconds(scan_time)),
+        format!("grep: scanned: {} bytes, {}", bytes_scanned, throughput),
+    ]
+}
+
+/// Returns the lines of the `--report-encoding-issues` report with the files
+/// that are not valid UTF-8.
+fn format_encoding_report(files: &[String]) -> Vec<String> {
+    files
+        .iter()
+        .map(|file| format!("grep: {}: invalid UTF-8 sequences", file))
+        .collect()
+}
+
+#[cfg(test)]
+mod grep_ts {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io;
+    use tempfile::TempDir;
+
+    /// Creates a file with the given contents inside `dir` and returns its path.
+    fn write_file(dir: &TempDir, name: &str, contents: &[u8]) -> String {
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn ts_output_formatter_new() {
+        let pattern = "the pattern";
+        let of = OutputFormatter::new(pattern);
+        assert_eq!(false, of.ignore_match);
+        assert_eq!(false, of.has_line_numbers);
+        assert_eq!(false, of.with_file_name);
+        assert_eq!(false, of.only_file_names);
+        assert_eq!(false, of.only_line_count);
+        assert_eq!(vec![pattern], of.patterns);
+        assert_eq!(0usize, of.inputs.len());
+    }
+
+    #[test]
+    fn ts_read_arguments() {
+        let pattern = "the pattern";
+        // load the pattern and stdin
+        let of = read_arguments(vec!["grep", "-e", "the pattern"]);
+
+        assert_eq!(false, of.ignore_match);
+        assert_eq!(false, of.has_line_numbers);
+        assert_eq!(false, of.with_file_name);
+        assert_eq!(false, of.only_file_names);
+        assert_eq!(false, of.only_line_count);
+        assert_eq!(vec![pattern], of.patterns);
+        assert_eq!(1usize, of.inputs.len());
+        assert_eq!("-", of.inputs[0]);
+
+        // load input files
+        let of = read_arguments(vec!["grep", "-e", "the pattern", "f1", "f2", "f3"]);
+
+        assert_eq!(false, of.ignore_match);
+        assert_eq!(false, of.has_line_numbers);
+        assert_eq!(true, of.with_file_name);
+        assert_eq!(false, of.only_file_names);
+        assert_eq!(false, of.only_line_count);
+        assert_eq!(vec![pattern], of.patterns);
+        assert_eq!(3usize, of.inputs.len());
+
+        for i in 0..of.inputs.len() {
+            assert_eq!(format!("f{}", i + 1), of.inputs[i]);
+        }
+
+        // check all flags
+        let of = read_arguments(vec![
+            "grep",
+            "-v",
+            "-n",
+            "-l",
+            "-c",
+            "-H",
+            "-e",
+            "the pattern",
+            "f1",
+            "f2",
+            "f3",
+        ]);
+
+        assert_eq!(true, of.ignore_match);
+        assert_eq!(true, of.has_line_numbers);
+        assert_eq!(true, of.with_file_name);
+        assert_eq!(true, of.only_file_names);
+        assert_eq!(true, of.only_line_count);
+        assert_eq!(vec![pattern], of.patterns);
+        assert_eq!(3usize, of.inputs.len());
+
+        for i in 0..of.inputs.len() {
+            assert_eq!(format!("f{}", i + 1), of.inputs[i]);
+        }
+    }
+
+    #[test]
+    fn ts_build_regex() {
+        let of = OutputFormatter::new("lor(em");
+        match build_regex(&of) {
+            Ok(_) => panic!(),
+            Err(err) => assert_eq!(ErrCode::InvalidRegularExpression, err),
+        }
+    }
+
+    #[test]
+    fn ts_build_regex_engine() {
+        // auto detects the literal patterns
+        assert_eq!(Engine::Literal, select_engine(Engine::Auto, "lorem ipsum"));
+        assert_eq!(Engine::Regex, select_engine(Engine::Auto, "lorem.*"));
+
+        // a regex looking pattern matched literally
+        let of = read_arguments(vec!["grep", "--engine=literal", "-e", "a.c"]);
+        assert_eq!(Engine::Literal, select_engine(of.engine, &of.patterns[0]));
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"xa.cx"));
+        assert!(!re.is_match(b"abc"));
+
+        // and as a regular expression
+        let of = read_arguments(vec!["grep", "--engine=regex", "-e", "a.c"]);
+        assert_eq!(Engine::Regex, select_engine(of.engine, &of.patterns[0]));
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"abc"));
+
+        // an invalid regular expression is valid as a literal
+        let of = read_arguments(vec!["grep", "--engine=literal", "-e", "lor(em"]);
+        assert!(build_regex(&of).unwrap().is_match(b"lor(em"));
+    }
+
+    #[test]
+    fn ts_ignore_case() {
+        let dir = TempDir::new().unwrap();
+        let input = write_file(&dir, "f1", b"lorem\nipsum\n");
+
+        let of = read_arguments(vec!["grep", "-e", "LOREM"]);
+        assert!(!of.ignore_case);
+        let re = build_regex(&of).unwrap();
+        assert!(!re.is_match(b"lorem"));
+        let files = find_matching_files(&vec![input.clone()], &re, &of, &mut false).unwrap();
+        assert!(files.is_empty());
+
+        for args in [
+            vec!["grep", "-i", "-e", "LOREM"],
+            vec!["grep", "--ignore-case", "--engine=regex", "-e", "LOR.M"],
+        ]
+        .iter()
+        {
+            let of = read_arguments(args);
+            assert!(of.ignore_case);
+            let re = build_regex(&of).unwrap();
+            assert!(re.is_match(b"lorem"));
+
+            // -l
+            let files = find_matching_files(&vec![input.clone()], &re, &of, &mut false).unwrap();
+            assert_eq!(vec![input.clone()], files);
+
+            // line printing
+            let mut output: Vec<u8> = Vec::new();
+            let reader = open_input(&input, &of).unwrap();
+            assert_eq!(
+                1,
+                print_matches(&mut output, reader, &input, &re, &of).unwrap()
+            );
+            assert_eq!("lorem\n", String::from_utf8(output).unwrap());
+        }
+
+        // -c
+        let of = read_arguments(vec!["grep", "-i", "-c", "-e", "LOREM"]);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let reader = open_input(&input, &of).unwrap();
+        assert_eq!(
+            1,
+            print_matches(&mut output, reader, &input, &re, &of).unwrap()
+        );
+    }
+
+    #[test]
+    fn ts_format_stats() {
+        assert_eq!(
+            vec![
+                "grep: compile: 1.500 ms",
+                "grep: scan: 250.000 ms",
+                "grep: scanned: 5000000 bytes, 20.00 MB/s",
+            ],
+            format_stats(
+                Duration::from_micros(1500),
+                Duration::from_millis(250),
+                5_000_000
+            )
+        );
+        assert_eq!(
+            "grep: scanned: 0 bytes, -",
+            format_stats(Duration::from_secs(0), Duration::from_secs(0), 0)[2]
+        );
+
+        // the bytes of every input are counted
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let f2 = write_file(&dir, "f2", b"dolor\n");
+        let of = read_arguments(vec!["grep", "--stats", "-e", "ipsum"]);
+        assert!(of.stats);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let summary = search_inputs(&mut output, &[f1, f2], &re, &of).unwrap();
+        assert_eq!(18, summary.bytes_scanned);
+    }
+
+    #[test]
+    fn ts_run_exit_status() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let f2 = write_file(&dir, "f2", b"dolor\n");
+        let missing = dir.path().join("missing").display().to_string();
+        let grep = |args: Vec<&str>| {
+            let mut output: Vec<u8> = Vec::new();
+            let status = run(&mut output, read_arguments(args));
+            (status, String::from_utf8(output).unwrap())
+        };
+
+        assert_eq!(
+            (0, String::from("ipsum\n")),
+            grep(vec!["grep", "-e", "ipsum", &f1])
+        );
+        assert_eq!(
+            (1, String::new()),
+            grep(vec!["grep", "-e", "sit", &f1, &f2])
+        );
+        assert_eq!(0, grep(vec!["grep", "-v", "-e", "lorem", &f1]).0);
+        assert_eq!(1, grep(vec!["grep", "-c", "-e", "sit", &f1]).0);
+        assert_eq!(
+            (0, format!("{}\n", f2)),
+            grep(vec!["grep", "-l", "-e", "dolor", &f1, &f2])
+        );
+        assert_eq!(1, grep(vec!["grep", "-l", "-e", "sit", &f1, &f2]).0);
+        assert_eq!(
+            (0, String::new()),
+            grep(vec!["grep", "-q", "-e", "dolor", &f1, &f2])
+        );
+        assert_eq!(1, grep(vec!["grep", "-q", "-e", "sit", &f1, &f2]).0);
+
+        // an error exits with 2, even if a line is selected
+        assert_eq!(2, grep(vec!["grep", "-e", "(", &f1]).0);
+        assert_eq!(2, grep(vec!["grep", "-e", "lorem", &missing]).0);
+        assert_eq!(2, grep(vec!["grep", "-e", "lorem", &missing, &f1]).0);
+        assert_eq!(2, grep(vec!["grep", "-l", "-e", "lorem", &missing, &f1]).0);
+        assert_eq!(2, grep(vec!["grep", "-f", &missing, &f1]).0);
+    }
+
+    #[test]
+    fn ts_quiet() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let f2 = write_file(&dir, "f2", b"dolor\n");
+        let missing = dir.path().join("missing").display().to_string();
+
+        let of = read_arguments(vec!["grep", "-q", "-e", "dolor"]);
+        assert!(of.quiet);
+        let re = build_regex(&of).unwrap();
+        let mut failed = false;
+        let found = has_selected_line(&[f1.clone(), f2.clone()], &re, &of, &mut failed).unwrap();
+        assert!(found);
+        assert_eq!(0, quiet_exit_code(found, failed));
+
+        // no match
+        let re = Regex::new("sit").unwrap();
+        let found = has_selected_line(&[f1.clone(), f2.clone()], &re, &of, &mut failed).unwrap();
+        assert!(!found);
+        assert_eq!(1, quiet_exit_code(found, failed));
+
+        // a match is a success even if an input fails, an error otherwise
+        let re = Regex::new("lorem").unwrap();
+        let found =
+            has_selected_line(&[missing.clone(), f1.clone()], &re, &of, &mut failed).unwrap();
+        assert!(failed);
+        assert_eq!(0, quiet_exit_code(found, failed));
+        assert_eq!(2, exit_code(found, failed));
+        let mut failed = false;
+        let found = has_selected_line(&[missing, f2], &re, &of, &mut failed).unwrap();
+        assert_eq!(2, quiet_exit_code(found, failed));
+
+        // the binary inputs have no match with -I
+        let binary = vec![write_file(&dir, "binary", b"lorem\0\n")];
+        let mut failed = false;
+        let of = read_arguments(vec!["grep", "-q", "-I", "-e", "lorem"]);
+        assert!(!has_selected_line(&binary, &re, &of, &mut failed).unwrap());
+        let inputs = vec![binary[0].clone(), f1.clone()];
+        assert!(has_selected_line(&inputs, &re, &of, &mut failed).unwrap());
+        let of = read_arguments(vec!["grep", "-q", "-e", "lorem"]);
+        assert!(has_selected_line(&binary, &re, &of, &mut failed).unwrap());
+        assert_eq!(1, exit_code(false, false));
+        assert_eq!(0, exit_code(true, false));
+
+        // the reading stops at the first selected line
+        let mut reader = io::Cursor::new(&b"lorem\nipsum\ndolor\n"[..]);
+        assert_eq!(
+            1,
+            match_lines(&mut reader, &re, false, Some(1), b'\n')
+                .collect::<Vec<_>>()
+                .len()
+        );
+        assert_eq!(6, reader.position());
+    }
+
+    #[test]
+    fn ts_null_data_only_matching() {
+        let of = read_arguments(vec!["grep", "-z", "-o", "-e", "[a-z]+[0-9]"]);
+        assert!(of.null_data);
+        assert_eq!(b'\0', of.terminator());
+        let re = build_regex(&of).unwrap();
+
+        // the matches of every record are NUL terminated, a newline is part of
+        // the record
+        let input = b"ab1 cd2\0x\0ef3\ngh4\0";
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(&input[..]), "-", &re, &of).unwrap();
+        assert_eq!(2, count);
+        assert_eq!(b"ab1\0cd2\0ef3\0gh4\0".to_vec(), output);
+
+        // a match does not span two records
+        let re = Regex::new("1 cd2.x").unwrap();
+        assert!(!find_match(io::Cursor::new(&input[..]), &re, false, b'\0').unwrap());
+        assert!(find_match(io::Cursor::new(&input[..]), &re, false, b'\n').unwrap());
+    }
+
+    #[test]
+    fn ts_null_data_records() {
+        let input = b"a\0b\0c";
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let re = build_regex(&of).unwrap();
+            let mut output: Vec<u8> = Vec::new();
+            let count =
+                print_matches(&mut output, io::Cursor::new(&input[..]), "-", &re, &of).unwrap();
+            (count, output)
+        };
+
+        // every record is matched on its own, the last one is not terminated
+        assert_eq!(
+            (1, b"2: b\0".to_vec()),
+            print(vec!["grep", "-z", "-n", "-e", "^b$"])
+        );
+        assert_eq!(
+            (2, b"a\0c\0".to_vec()),
+            print(vec!["grep", "--null-data", "-v", "-e", "b"])
+        );
+        assert_eq!(
+            (3, b"a\0b\0c\0".to_vec()),
+            print(vec!["grep", "-z", "-e", "^[abc]$"])
+        );
+        // without -z the input is a single line
+        assert_eq!((0, Vec::new()), print(vec!["grep", "-e", "^b$"]));
+    }
+
+    #[test]
+    fn ts_files_with_and_without_match() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let f2 = write_file(&dir, "f2", b"dolor\n");
+        let inputs = vec![f1.clone(), f2.clone()];
+        let mut failed = false;
+
+        let of = read_arguments(vec!["grep", "-l", "-e", "ipsum"]);
+        let re = build_regex(&of).unwrap();
+        let files = find_matching_files(&inputs, &re, &of, &mut failed).unwrap();
+        assert_eq!(vec![f1.clone()], files);
+
+        let of = read_arguments(vec!["grep", "-L", "-e", "ipsum"]);
+        assert!(of.files_without_match);
+        let files = find_matching_files(&inputs, &re, &of, &mut failed).unwrap();
+        assert_eq!(vec![f2.clone()], files);
+
+        let of = read_arguments(vec!["grep", "--files-without-match", "-e", "lorem|dolor"]);
+        let re = build_regex(&of).unwrap();
+        assert!(find_matching_files(&inputs, &re, &of, &mut failed)
+            .unwrap()
+            .is_empty());
+        assert!(!failed);
+
+        // with -v the files with a line that does not match are listed
+        let f3 = write_file(&dir, "f3", b"ipsum\n");
+        let inputs = vec![f1.clone(), f2.clone(), f3.clone()];
+        let of = read_arguments(vec!["grep", "-lv", "-e", "ipsum"]);
+        let re = build_regex(&of).unwrap();
+        let files = find_matching_files(&inputs, &re, &of, &mut failed).unwrap();
+        assert_eq!(vec![f1, f2], files);
+        let of = read_arguments(vec!["grep", "-L", "-v", "-e", "ipsum"]);
+        let files = find_matching_files(&inputs, &re, &of, &mut failed).unwrap();
+        assert_eq!(vec![f3], files);
+
+        // -l and -L are mutually exclusive
+        assert!(build_app()
+            .get_matches_from_safe(vec!["grep", "-l", "-L", "-e", "lorem"])
+            .is_err());
+
+        assert_eq!("(standard input)", input_name("-"));
+        assert_eq!("f1", input_name("f1"));
+    }
+
+    #[test]
+    fn ts_find_matching_files_missing_input() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing").display().to_string();
+        let valid = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let of = OutputFormatter::new("ipsum");
+        let re = build_regex(&of).unwrap();
+
+        // the valid file is still searched
+        let mut failed = false;
+        let inputs = vec![missing.clone(), valid.clone()];
+        let files = find_matching_files(&inputs, &re, &of, &mut failed).unwrap();
+        assert_eq!(vec![valid], files);
+        assert!(failed);
+
+        match open_input(&missing, &of) {
+            Ok(_) => panic!(),
+            Err(err) => assert_eq!(ErrCode::ErrorCannotOpenFileForReading, err),
+        }
+        let err = File::open(&missing).unwrap_err();
+        assert_eq!("No such file or directory", describe_error(&err));
+    }
+
+    #[test]
+    fn ts_no_unicode() {
+        let of = read_arguments(vec!["grep", "--no-unicode", "-e", r"^\w+$"]);
+        assert!(of.no_unicode);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"cafe"));
+        // the two bytes of é are not word characters
+        assert!(!re.is_match("café".as_bytes()));
+        let re = build_regex(&read_arguments(vec!["grep", "-e", r"^\w+$"])).unwrap();
+        assert!(re.is_match("café".as_bytes()));
+
+        // word boundaries and case folding are ASCII only
+        let of = read_arguments(vec!["grep", "--no-unicode", "-w", "-i", "-e", "caf"]);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match("CAFé".as_bytes()));
+        assert!(!re.is_match(b"cafe"));
+        let of = read_arguments(vec!["grep", "-w", "-i", "-e", "caf"]);
+        assert!(!build_regex(&of).unwrap().is_match("CAFé".as_bytes()));
+
+        // `.` and negated classes match a single byte of é
+        let of = read_arguments(vec!["grep", "--no-unicode", "-e", "^caf.$"]);
+        assert!(!build_regex(&of).unwrap().is_match("café".as_bytes()));
+        let of = read_arguments(vec!["grep", "--no-unicode", "-e", "^caf..$"]);
+        assert!(build_regex(&of).unwrap().is_match("café".as_bytes()));
+        let of = read_arguments(vec!["grep", "--no-unicode", "-e", "^caf[^a][^a]$"]);
+        assert!(build_regex(&of).unwrap().is_match("café".as_bytes()));
+        let of = read_arguments(vec!["grep", "-e", "^caf.$"]);
+        assert!(build_regex(&of).unwrap().is_match("café".as_bytes()));
+
+        // no pattern still matches nothing
+        let mut of = read_arguments(vec!["grep", "--no-unicode", "-e", "caf"]);
+        of.patterns.clear();
+        assert!(!build_regex(&of).unwrap().is_match("café".as_bytes()));
+    }
+
+    #[test]
+    fn ts_multiline_dotall() {
+        let input = "lorem foo\nbar ipsum\ndolor\nfoo bar\n";
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let re = build_regex(&of).unwrap();
+            let mut output: Vec<u8> = Vec::new();
+            let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+
+        // `.` matches the newline only with --dotall, which implies -U
+        let of = read_arguments(vec!["grep", "--dotall", "-e", "foo.bar"]);
+        assert!(of.multiline && of.dotall);
+        assert_eq!(
+            (3, String::from("lorem foo\nbar ipsum\nfoo bar\n")),
+            print(vec!["grep", "--dotall", "-e", "foo.bar"])
+        );
+        assert_eq!(
+            (1, String::from("foo bar\n")),
+            print(vec!["grep", "-U", "-e", "foo.bar"])
+        );
+        assert_eq!(
+            (1, String::from("foo bar\n")),
+            print(vec!["grep", "-e", "foo.bar"])
+        );
+
+        // every match with the number and offset where it starts
+        assert_eq!(
+            (3, String::from("1: 6: foo\nbar\n4: 26: foo bar\n")),
+            print(vec!["grep", "--dotall", "-o", "-n", "-b", "-e", "foo.bar"])
+        );
+        assert_eq!(
+            (2, String::from("1: lorem foo\n2: bar ipsum\n")),
+            print(vec!["grep", "-U", "-n", "-m", "1", "-e", "foo\\sbar"])
+        );
+        // `^` and `$` match at every line
+        assert_eq!(
+            (1, String::from("dolor\n")),
+            print(vec!["grep", "-U", "-e", "^dolor$"])
+        );
+
+        // the files are searched as a whole too
+        let dir = TempDir::new().unwrap();
+        let file = write_file(&dir, "f1", input.as_bytes());
+        let of = read_arguments(vec!["grep", "--dotall", "-l", "-e", "ipsum.dolor", &file]);
+        let re = build_regex(&of).unwrap();
+        let mut failed = false;
+        assert_eq!(
+            vec![file.clone()],
+            find_matching_files(&of.inputs, &re, &of, &mut failed).unwrap()
+        );
+        assert!(has_selected_line(&of.inputs, &re, &of, &mut failed).unwrap());
+        let of = read_arguments(vec!["grep", "-l", "-e", "ipsum.dolor", &file]);
+        let re = build_regex(&of).unwrap();
+        assert!(!has_selected_line(&of.inputs, &re, &of, &mut failed).unwrap());
+
+        // the options that work line by line are rejected
+        for option in ["-v", "-A1", "--passthru", "-z"].iter() {
+            let args = vec!["grep", "--dotall", option, "-e", "foo.bar"];
+            assert!(build_app().get_matches_from_safe(args).is_err());
+            let args = vec!["grep", "-U", option, "-e", "foo.bar"];
+            assert!(build_app().get_matches_from_safe(args).is_err());
+        }
+    }
+
+    #[test]
+    fn ts_word_match() {
+        let of = read_arguments(vec!["grep", "-w", "-e", "foo"]);
+        assert!(of.word_match);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"a foo b"));
+        assert!(re.is_match(b"foo"));
+        assert!(re.is_match(b"(foo)"));
+        assert!(!re.is_match(b"foobar"));
+        assert!(!re.is_match(b"barfoo"));
+
+        // the boundaries apply to all the alternatives
+        let of = read_arguments(vec!["grep", "-w", "-e", "foo|bar"]);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"a bar b"));
+        assert!(!re.is_match(b"foobar"));
+        assert!(!re.is_match(b"barbaz"));
+
+        // composes with -i
+        let of = read_arguments(vec!["grep", "-w", "-i", "-e", "foo"]);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"a FOO b"));
+        assert!(!re.is_match(b"FOOBAR"));
+    }
+
+    #[test]
+    fn ts_line_match() {
+        let of = read_arguments(vec!["grep", "-x", "-e", "lorem"]);
+        assert!(of.line_match);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"lorem"));
+        assert!(!re.is_match(b"lorem ipsum"));
+        assert!(!re.is_match(b"dolor lorem"));
+
+        let input = b"lorem\r\nlorem ipsum\r\nLOREM\r\n";
+        let lines =
+            match_lines(io::Cursor::new(input), &re, false, None, b'\n').collect::<Vec<_>>();
+        assert_eq!(vec![(1, String::from("lorem"))], lines);
+
+        // composes with -i and -v
+        let of = read_arguments(vec!["grep", "-x", "-i", "-e", "lorem|ipsum"]);
+        let re = build_regex(&of).unwrap();
+        let lines =
+            match_lines(io::Cursor::new(input), &re, false, None, b'\n').collect::<Vec<_>>();
+        let numbers: Vec<usize> = lines.iter().map(|line| line.0).collect();
+        assert_eq!(vec![1, 3], numbers);
+        let lines = match_lines(io::Cursor::new(input), &re, true, None, b'\n').collect::<Vec<_>>();
+        assert_eq!(vec![(2, String::from("lorem ipsum"))], lines);
+    }
+
+    #[test]
+    fn ts_fixed_strings() {
+        let of = read_arguments(vec!["grep", "-F", "-e", "a.b"]);
+        assert_eq!(Engine::Literal, of.engine);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"a.b"));
+        assert!(!re.is_match(b"axb"));
+
+        let of = read_arguments(vec!["grep", "--fixed-strings", "-e", "a.b[c]"]);
+        assert!(build_regex(&of).unwrap().is_match(b"xa.b[c]x"));
+
+        // composes with -i and -w
+        let of = read_arguments(vec!["grep", "-F", "-i", "-w", "-e", "a.b"]);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"x A.B y"));
+        assert!(!re.is_match(b"xa.by"));
+        assert!(!re.is_match(b"x AXB y"));
+    }
+
+    #[test]
+    fn ts_multiple_patterns() {
+        let of = read_arguments(vec!["grep", "-e", "foo", "-e", "bar", "f1"]);
+        assert_eq!(vec!["foo", "bar"], of.patterns);
+        assert_eq!(vec!["f1"], of.inputs);
+        let re = build_regex(&of).unwrap();
+        let input = b"a foo\nlorem\nbar b\nfoobar\n";
+        let lines =
+            match_lines(io::Cursor::new(input), &re, false, None, b'\n').collect::<Vec<_>>();
+        let numbers: Vec<usize> = lines.iter().map(|line| line.0).collect();
+        assert_eq!(vec![1, 3, 4], numbers);
+
+        // the engine is selected for every pattern
+        let of = read_arguments(vec!["grep", "-e", "a.c", "-e", "x+"]);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"abc"));
+        assert!(re.is_match(b"xxx"));
+        let of = read_arguments(vec!["grep", "-F", "-e", "a.c", "-e", "x+"]);
+        let re = build_regex(&of).unwrap();
+        assert!(!re.is_match(b"abc xxx"));
+        assert!(re.is_match(b"x+"));
+
+        // -x applies to all the patterns
+        let of = read_arguments(vec!["grep", "-x", "-e", "foo", "-e", "bar"]);
+        let re = build_regex(&of).unwrap();
+        assert!(re.is_match(b"bar"));
+        assert!(!re.is_match(b"foobar"));
+    }
+
+    #[test]
+    fn ts_read_pattern_files() {
+        let dir = TempDir::new().unwrap();
+        let patterns = write_file(&dir, "patterns", b"foo\nb.r\n");
+        let input = b"a foo\nlorem\nbxr b\nipsum\n";
+
+        let mut of = read_arguments(vec!["grep", "-f", &patterns, "-e", "ipsum", "f1"]);
+        assert_eq!(vec![patterns.clone()], of.pattern_files);
+        read_pattern_files(&mut of).unwrap();
+        assert_eq!(vec!["ipsum", "foo", "b.r"], of.patterns);
+        let re = build_regex(&of).unwrap();
+        let lines =
+            match_lines(io::Cursor::new(input), &re, false, None, b'\n').collect::<Vec<_>>();
+        let numbers: Vec<usize> = lines.iter().map(|line| line.0).collect();
+        assert_eq!(vec![1, 3, 4], numbers);
+
+        // an empty line matches every line
+        let empty_line = write_file(&dir, "empty_line", b"foo\n\n");
+        let mut of = read_arguments(vec!["grep", "-f", &empty_line, "f1"]);
+        read_pattern_files(&mut of).unwrap();
+        let re = build_regex(&of).unwrap();
+        assert_eq!(
+            4,
+            match_lines(io::Cursor::new(input), &re, false, None, b'\n')
+                .collect::<Vec<_>>()
+                .len()
+        );
+
+        // an empty file matches nothing
+        let empty = write_file(&dir, "empty", b"");
+        let mut of = read_arguments(vec!["grep", "-f", &empty, "f1"]);
+        read_pattern_files(&mut of).unwrap();
+        let re = build_regex(&of).unwrap();
+        assert!(match_lines(io::Cursor::new(input), &re, false, None, b'\n')
+            .collect::<Vec<_>>()
+            .is_empty());
+
+        // the standard input is read once
+        let mut of = read_arguments(vec!["grep", "-f", "-"]);
+        assert_eq!(
+            Err(ErrCode::ErrorArgumentParsing),
+            read_pattern_files(&mut of)
+        );
+        let mut of = read_arguments(vec!["grep", "-f", "missing"]);
+        assert_eq!(
+            Err(ErrCode::ErrorCannotOpenFileForReading),
+            read_pattern_files(&mut of)
+        );
+    }
+
+    #[test]
+    fn ts_regex_compiled_once_for_multiple_files() {
+        let dir = TempDir::new().unwrap();
+        let mut of = OutputFormatter::new("ipsum");
+        of.inputs.push(write_file(&dir, "f1", b"lorem\nipsum\n"));
+        of.inputs.push(write_file(&dir, "f2", b"dolor\n"));
+        of.inputs.push(write_file(&dir, "f3", b"ipsum dolor\n"));
+
+        // a single compiled expression is shared by reference by all the files
+        let re = build_regex(&of).unwrap();
+        let files = find_matching_files(&of.inputs, &re, &of, &mut false).unwrap();
+        assert_eq!(vec![of.inputs[0].clone(), of.inputs[2].clone()], files);
+
+        // run compiles the expression once for all the files
+        for option in ["-H", "-l", "-c", "-q"].iter() {
+            let mut args = vec!["grep", option, "-e", "ipsum"];
+            args.extend(of.inputs.iter().map(|input| input.as_str()));
+            let builds = REGEX_BUILDS.with(Cell::get);
+            assert_eq!(0, run(&mut Vec::new(), read_arguments(args)));
+            assert_eq!(builds + 1, REGEX_BUILDS.with(Cell::get));
         }
-    };
+    }
 
-    // Fast implementation for finding files that match the expression
-    if output_formatter.only_file_names {
-        match find_matching_files(&output_formatter.inputs, &re, output_formatter.ignore_match) {
-            Ok(matched_files) => {
-                for file_name in matched_files {
-                    println!("{}", file_name.as_str());
-                }
-                return;
-            }
-            Err(err) => {
-                eprintln!("Error");
-                process::exit(err as i32);
-            }
+    #[test]
+    fn ts_read_arguments_binary_files() {
+        let of = read_arguments(vec!["grep", "-e", "p"]);
+        assert_eq!(BinaryFiles::Binary, of.binary_files);
+
+        let of = read_arguments(vec!["grep", "--binary-files=text", "-e", "p"]);
+        assert_eq!(BinaryFiles::Text, of.binary_files);
+
+        let of = read_arguments(vec!["grep", "--binary-files", "without-match", "-e", "p"]);
+        assert_eq!(BinaryFiles::WithoutMatch, of.binary_files);
+
+        let of = read_arguments(vec!["grep", "-a", "-e", "p"]);
+        assert_eq!(BinaryFiles::Text, of.binary_files);
+
+        let of = read_arguments(vec!["grep", "-I", "-e", "p"]);
+        assert_eq!(BinaryFiles::WithoutMatch, of.binary_files);
+    }
+
+    #[test]
+    fn ts_print_matches_binary_files() {
+        let re = Regex::new("ipsum").unwrap();
+        let input = b"lorem\nipsum\x00dolor\nsit\n";
+        let mut of = OutputFormatter::new("ipsum");
+
+        // binary: report the match without printing the line
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(
+            "Binary file f1 matches\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // text: print the matching line
+        of.binary_files = BinaryFiles::Text;
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(b"ipsum\x00dolor\n".to_vec(), output);
+
+        // without-match: the binary file never matches
+        of.binary_files = BinaryFiles::WithoutMatch;
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(0, count);
+        assert!(output.is_empty());
+
+        // a NUL byte after the first buffer stops the output at its line
+        let reader = io::BufReader::with_capacity(8, &input[..]);
+        let of = read_arguments(vec!["grep", "-n", "-e", "lorem|ipsum"]);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, reader, "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+        assert_eq!(
+            "1: lorem\nBinary file f1 matches\n",
+            String::from_utf8(output).unwrap()
+        );
+        let reader = io::BufReader::with_capacity(8, &input[..]);
+        let of = read_arguments(vec!["grep", "--text", "-e", "lorem|ipsum"]);
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, reader, "f1", &re, &of).unwrap();
+        assert_eq!(b"lorem\nipsum\x00dolor\n".to_vec(), output);
+
+        // files without NUL bytes are always printed
+        let of = OutputFormatter::new("ipsum");
+        let re = Regex::new("ipsum").unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(b"ipsum\n"), "f1", &re, &of).unwrap();
+        assert_eq!("ipsum\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_match_lines_streaming() {
+        // the lines are read as the iterator advances, the rest of an endless
+        // input is never read
+        let re = Regex::new("ipsum").unwrap();
+        let endless =
+            io::BufReader::new(io::Cursor::new(&b"lorem\nipsum\n"[..]).chain(io::repeat(b'x')));
+        let mut lines = match_lines(endless, &re, false, None, b'\n');
+        assert_eq!(Some((2, String::from("ipsum"))), lines.next());
+
+        let of = read_arguments(vec!["grep", "-A", "1", "-e", "ipsum"]);
+        let endless =
+            io::BufReader::new(io::Cursor::new(&b"ipsum\nlorem\n"[..]).chain(io::repeat(b'x')));
+        let lines: Vec<(usize, u64, Vec<u8>, bool)> = match_lines_with_context(endless, &re, &of)
+            .take(2)
+            .collect();
+        assert_eq!(
+            vec![
+                (1, 0, b"ipsum".to_vec(), true),
+                (2, 6, b"lorem".to_vec(), false)
+            ],
+            lines
+        );
+
+        // the output and the counts keep the order of the input
+        let input = b"ipsum 1\nlorem\nipsum 2\ndolor\nsit\namet\nipsum 3\n";
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            let count =
+                print_matches(&mut output, io::Cursor::new(&input[..]), "f1", &re, &of).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+        assert_eq!(
+            (3, String::from("1: ipsum 1\n3: ipsum 2\n7: ipsum 3\n")),
+            print(vec!["grep", "-n", "-e", "ipsum"])
+        );
+        assert_eq!(
+            (
+                3,
+                String::from("ipsum 1\nlorem\nipsum 2\ndolor\n--\nipsum 3\n")
+            ),
+            print(vec!["grep", "-A", "1", "-e", "ipsum"])
+        );
+        assert_eq!((3, String::new()), print(vec!["grep", "-c", "-e", "ipsum"]));
+        assert_eq!(
+            (4, String::new()),
+            print(vec!["grep", "-c", "-v", "-e", "ipsum"])
+        );
+
+        // binary input is counted without the binary file message
+        let of = read_arguments(vec!["grep", "-c", "-e", "ipsum"]);
+        let binary_input = b"ipsum\0\nlorem\nipsum\n";
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(
+            &mut output,
+            io::Cursor::new(&binary_input[..]),
+            "f1",
+            &re,
+            &of,
+        )
+        .unwrap();
+        assert_eq!(2, count);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn ts_match_lines_max_count() {
+        let re = Regex::new("ipsum").unwrap();
+        let input = b"ipsum 1\nipsum 2\nlorem\nipsum 3\nipsum 4\nipsum 5\n";
+
+        // the reading stops at the second matching line
+        let mut reader = io::Cursor::new(&input[..]);
+        let lines = match_lines(&mut reader, &re, false, Some(2), b'\n').collect::<Vec<_>>();
+        assert_eq!(
+            vec![(1, String::from("ipsum 1")), (2, String::from("ipsum 2"))],
+            lines
+        );
+        assert_eq!(16, reader.position());
+
+        let of = read_arguments(vec!["grep", "-m", "2", "-e", "ipsum"]);
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+        assert_eq!("ipsum 1\nipsum 2\n", String::from_utf8(output).unwrap());
+
+        // with -v the lines that do not match are counted
+        let re = Regex::new("[12]").unwrap();
+        let of = read_arguments(vec!["grep", "-v", "--max-count=2", "-e", "[12]"]);
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!("lorem\nipsum 3\n", String::from_utf8(output).unwrap());
+
+        // -m 0 reads nothing
+        let mut reader = io::Cursor::new(&input[..]);
+        assert!(match_lines(&mut reader, &re, false, Some(0), b'\n')
+            .collect::<Vec<_>>()
+            .is_empty());
+        assert_eq!(0, reader.position());
+    }
+
+    #[test]
+    fn ts_print_matches_count_with_max_count() {
+        let re = Regex::new("ipsum").unwrap();
+        let input = b"ipsum 1\nipsum 2\nlorem\nipsum 3\nipsum 4\nipsum 5\n";
+
+        let of = read_arguments(vec!["grep", "-c", "-m", "2", "-e", "ipsum"]);
+        assert_eq!(Some(2), of.max_count);
+
+        // the count is capped at the maximum number of matches
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+        assert!(output.is_empty());
+
+        // without -m all the matches are counted
+        let of = read_arguments(vec!["grep", "-c", "-e", "ipsum"]);
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(5, count);
+    }
+
+    #[test]
+    fn ts_highlight_matches() {
+        let re = Regex::new("o").unwrap();
+        assert_eq!(
+            "l\x1b[01;31mo\x1b[0mrem d\x1b[01;31mo\x1b[0ml\x1b[01;31mo\x1b[0mr",
+            highlight_matches("lorem dolor", &re)
+        );
+        assert_eq!("ipsum", highlight_matches("ipsum", &re));
+    }
+
+    #[test]
+    fn ts_print_matches_color() {
+        let re = Regex::new("o+").unwrap();
+        let input = b"lorem\nipsum\ndoolor\n";
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // every match is highlighted, the prefix is not
+        assert_eq!(
+            "f1:1: l\x1b[01;31mo\x1b[0mrem\nf1:3: d\x1b[01;31moo\x1b[0ml\x1b[01;31mo\x1b[0mr\n",
+            print(vec!["grep", "-n", "-H", "--color=always", "-e", "o+"])
+        );
+        assert_eq!(
+            "lorem\ndoolor\n",
+            print(vec!["grep", "--colour=never", "-e", "o+"])
+        );
+
+        // the value is required to follow an equal sign, --color alone is auto
+        let of = read_arguments(vec!["grep", "--color", "-e", "o+"]);
+        assert_eq!(io::stdout().is_terminal(), of.color);
+        assert!(!read_arguments(vec!["grep", "-e", "o+"]).color);
+        let of = read_arguments(vec!["grep", "--color", "always", "-e", "o+"]);
+        assert_eq!(vec!["always"], of.inputs);
+        assert!(build_app()
+            .get_matches_from_safe(vec!["grep", "--color=sometimes", "-e", "o+"])
+            .is_err());
+    }
+
+    #[test]
+    fn ts_print_matches_passthru() {
+        let re = Regex::new("ipsum").unwrap();
+        let input = b"lorem\nipsum dolor\nsit\n";
+        let of = read_arguments(vec!["grep", "--passthru", "--color=always", "-e", "ipsum"]);
+        assert!(of.passthru);
+        assert!(of.color);
+
+        // non matching lines pass through, the matches are highlighted
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(
+            "lorem\n\x1b[01;31mipsum\x1b[0m dolor\nsit\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // without color the input is printed unchanged
+        let of = read_arguments(vec!["grep", "--passthru", "--color=never", "-e", "ipsum"]);
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(input.to_vec(), output);
+    }
+
+    #[test]
+    fn ts_print_matches_passthru_invert() {
+        let input = b"lorem\nipsum dolor\nsit\nipsum\n";
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let re = build_regex(&of).unwrap();
+            let mut output: Vec<u8> = Vec::new();
+            let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+
+        // the lines without a match are selected and nothing is highlighted
+        assert_eq!(
+            (2, String::from("lorem\nipsum dolor\nsit\nipsum\n")),
+            print(vec![
+                "grep",
+                "-v",
+                "--passthru",
+                "--color=always",
+                "-e",
+                "ipsum"
+            ])
+        );
+        assert_eq!(
+            (
+                2,
+                String::from("1: lorem\n2- ipsum dolor\n3: sit\n4- ipsum\n")
+            ),
+            print(vec!["grep", "-v", "-n", "--passthru", "-e", "ipsum"])
+        );
+        // the exit status follows the selected lines
+        let (count, _) = print(vec!["grep", "-v", "--passthru", "-e", "."]);
+        assert_eq!(0, count);
+        assert_eq!(1, exit_code(count > 0, false));
+    }
+
+    #[test]
+    fn ts_print_matches_field_separator() {
+        let re = Regex::new("ipsum").unwrap();
+        let input = b"lorem\nipsum dolor\n";
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // default separators for matching and context lines
+        assert_eq!(
+            "f1-1- lorem\nf1:2: ipsum dolor\n",
+            print(vec!["grep", "-H", "-n", "--passthru", "-e", "ipsum"])
+        );
+
+        // a custom separator is used for both
+        assert_eq!(
+            "f1|1|lorem\nf1|2|ipsum dolor\n",
+            print(vec![
+                "grep",
+                "-H",
+                "-n",
+                "--passthru",
+                "--field-separator=|",
+                "-e",
+                "ipsum"
+            ])
+        );
+        assert_eq!(
+            "f1\t2\tipsum dolor\n",
+            print(vec![
+                "grep",
+                "-H",
+                "-n",
+                "--field-separator",
+                "\t",
+                "-e",
+                "ipsum"
+            ])
+        );
+    }
+
+    #[test]
+    fn ts_print_matches_max_columns() {
+        let re = Regex::new("ipsum").unwrap();
+        let long_line = format!("{}ipsum{}ipsum", "a".repeat(100), "b".repeat(100));
+        let input = format!("lorem ipsum\n{}\n{}\n", long_line, "c".repeat(81));
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            let count = print_matches(
+                &mut output,
+                io::Cursor::new(input.as_bytes()),
+                "f1",
+                &re,
+                &of,
+            )
+            .unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+
+        // the long line still counts as a match
+        let (count, output) = print(vec!["grep", "--max-columns=80", "-e", "ipsum"]);
+        assert_eq!(2, count);
+        assert_eq!("lorem ipsum\n[Omitted long line with 2 matches]\n", output);
+
+        let (_, output) = print(vec![
+            "grep",
+            "--max-columns=80",
+            "--passthru",
+            "-e",
+            "ipsum",
+        ]);
+        assert_eq!(
+            "lorem ipsum\n[Omitted long line with 2 matches]\n[Omitted long context line]\n",
+            output
+        );
+
+        // no limit by default
+        let (_, output) = print(vec!["grep", "-e", "ipsum"]);
+        assert!(output.contains(&long_line));
+    }
+
+    #[test]
+    fn ts_print_matches_count_lines_not_occurrences() {
+        let re = Regex::new("o").unwrap();
+        let input = b"lorem\ndolor sit\nipsum\n";
+        let of = read_arguments(vec!["grep", "-c", "-e", "o"]);
+
+        // "dolor sit" has two occurrences but it is counted as one line
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+
+        // with -o the lines are still counted, not the matches
+        let of = read_arguments(vec!["grep", "-o", "-c", "-e", "o"]);
+        assert!(of.only_matching);
+        let input = b"lorem\nfoo\n";
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn ts_print_matches_only_matching() {
+        let of = read_arguments(vec!["grep", "-o", "-e", r"\d+"]);
+        assert!(of.only_matching);
+        let re = build_regex(&of).unwrap();
+        let input = b"a1b22c333\nlorem\n4\n";
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+        assert_eq!("1\n22\n333\n4\n", String::from_utf8(output).unwrap());
+
+        // every match with its line number and file name
+        let of = read_arguments(vec!["grep", "-o", "-n", "-H", "-e", r"\d+"]);
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(
+            "f1:1: 1\nf1:1: 22\nf1:1: 333\nf1:3: 4\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // the empty matches are not printed
+        let re = Regex::new(r"\d*").unwrap();
+        assert_eq!(
+            vec![(1, String::from("1")), (3, String::from("22"))],
+            match_texts("a1b22", &re, &of)
+        );
+
+        let args = vec!["grep", "-o", "-v", "-e", "lorem"];
+        assert!(build_app().get_matches_from_safe(args).is_err());
+    }
+
+    #[test]
+    fn ts_only_matching_nth() {
+        let input = "1 2 3\n4 5 6\n7\nno digits\n";
+        let re = Regex::new(r"\d+").unwrap();
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+
+        assert_eq!(
+            (3, String::from("2\n5\n")),
+            print(vec!["grep", "-o", "--nth=2", "-e", r"\d+"])
+        );
+        // a negative N counts from the end of the line
+        assert_eq!(
+            (3, String::from("3\n6\n7\n")),
+            print(vec!["grep", "-o", "--nth", "-1", "-e", r"\d+"])
+        );
+        assert_eq!(
+            (3, String::from("1\n4\n")),
+            print(vec!["grep", "-o", "--nth=-3", "-e", r"\d+"])
+        );
+        // the offset is the offset of the match
+        assert_eq!(
+            (2, String::from("2: 2\n8: 5\n")),
+            print(vec!["grep", "-o", "-b", "--nth=2", "-m", "2", "-e", r"\d+"])
+        );
+
+        for args in [
+            vec!["grep", "--nth=1", "-e", "x"],
+            vec!["grep", "-o", "--nth=0", "-e", "x"],
+            vec!["grep", "-o", "--nth=a", "-e", "x"],
+        ]
+        .iter()
+        {
+            assert!(build_app().get_matches_from_safe(args).is_err());
         }
     }
 
-    // More complex implementation for finding lines that match the expression
-    let mut line_count: usize = 0;
-    for input_file in &output_formatter.inputs {
-        // line number, line
-        let mut lines: Vec<(usize, String)> = Vec::new();
-        let current_file: String;
-        if input_file == "-" {
-            current_file = String::from("standard input");
-            let stdin = io::stdin();
-            let reader = stdin.lock();
-            match match_lines(reader, &re, output_formatter.ignore_match) {
-                Ok(lines_) => {
-                    for line in lines_ {
-                        lines.push((line.0, line.1));
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error");
-                    process::exit(err as i32);
-                }
-            }
-        } else {
-            current_file = input_file.to_string();
-            let f = File::open(input_file).unwrap();
-            let reader = BufReader::new(f);
-            match match_lines(reader, &re, output_formatter.ignore_match) {
-                Ok(lines_) => {
-                    for line in lines_ {
-                        lines.push((line.0, line.1));
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error");
-                    process::exit(err as i32);
-                }
-            }
+    #[test]
+    fn ts_only_matching_exit_code() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let missing = dir.path().join("missing").display().to_string();
+        let of = read_arguments(vec!["grep", "-o", "-e", r"\d+"]);
+        let re = build_regex(&of).unwrap();
+
+        // no matches is not an error
+        let mut output: Vec<u8> = Vec::new();
+        let summary = search_inputs(&mut output, std::slice::from_ref(&f1), &re, &of).unwrap();
+        assert!(output.is_empty());
+        assert!(!summary.failed);
+        assert_eq!(1, exit_code(summary.line_count > 0, summary.failed));
+
+        let mut output: Vec<u8> = Vec::new();
+        let summary = search_inputs(&mut output, &[missing], &re, &of).unwrap();
+        assert!(output.is_empty());
+        assert_eq!(2, exit_code(summary.line_count > 0, summary.failed));
+
+        let of = read_arguments(vec!["grep", "-o", "-e", "ips"]);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let summary = search_inputs(&mut output, &[f1], &re, &of).unwrap();
+        assert_eq!("ips\n", String::from_utf8(output).unwrap());
+        assert_eq!(0, exit_code(summary.line_count > 0, summary.failed));
+    }
+
+    #[test]
+    fn ts_print_matches_replace() {
+        let of = read_arguments(vec!["grep", "--replace", "$2:$1", "-e", r"(\w+)=(\d+)"]);
+        assert_eq!(Some(String::from("$2:$1")), of.replace);
+        let re = build_regex(&of).unwrap();
+        assert_eq!(vec!["1:a", "2:b"], expand_matches("a=1 b=2", &re, "$2:$1"));
+
+        let input = b"a=1 b=2\nlorem\nc=3\n";
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count);
+        assert_eq!("1:a\n2:b\n3:c\n", String::from_utf8(output).unwrap());
+
+        // with the line numbers of the matches
+        let of = read_arguments(vec!["grep", "-n", "--replace", "<$0>", "-e", r"\d"]);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(
+            "1: <1>\n1: <2>\n3: <3>\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // the empty matches are not written, like with -o
+        let of = read_arguments(vec!["grep", "--replace", "$1", "-e", "(o)?"]);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(b"foo\nbar\n"), "f1", &re, &of);
+        assert_eq!(2, count.unwrap());
+        assert_eq!("o\no\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_print_matches_context() {
+        let input: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let re = Regex::new("line 5$").unwrap();
+
+        let of = read_arguments(vec!["grep", "-n", "-C", "1", "-e", "line 5$"]);
+        assert_eq!((1, 1), (of.before_context, of.after_context));
+        let lines = match_lines_with_context(io::Cursor::new(&input), &re, &of).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (4, 21, b"line 4".to_vec(), false),
+                (5, 28, b"line 5".to_vec(), true),
+                (6, 35, b"line 6".to_vec(), false),
+            ],
+            lines
+        );
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(&input), "f1", &re, &of).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(
+            "4- line 4\n5: line 5\n6- line 6\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // -A and -B take precedence over -C
+        let of = read_arguments(vec!["grep", "-C", "3", "-A", "0", "-B", "2", "-e", "x"]);
+        assert_eq!((2, 0), (of.before_context, of.after_context));
+    }
+
+    #[test]
+    fn ts_print_matches_byte_offset() {
+        let input = "lorem\r\nipsum dolor\nsit ipsum\n";
+        let re = Regex::new("ipsum").unwrap();
+        let print = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // the offsets count the stripped terminators, `\r` included
+        assert_eq!(
+            "7: ipsum dolor\n19: sit ipsum\n",
+            print(vec!["grep", "-b", "-e", "ipsum"])
+        );
+        assert_eq!(
+            "f1:2: 7: ipsum dolor\nf1:3: 19: sit ipsum\n",
+            print(vec!["grep", "-H", "-n", "--byte-offset", "-e", "ipsum"])
+        );
+        // with -o the offset of every match
+        assert_eq!(
+            "7: ipsum\n23: ipsum\n",
+            print(vec!["grep", "-b", "-o", "-e", "ipsum"])
+        );
+        // the context lines
+        assert_eq!(
+            "0- lorem\n7: ipsum dolor\n",
+            print(vec!["grep", "-b", "-B", "1", "-m", "1", "-e", "ipsum"])
+        );
+
+        // the offsets of the matches count the bytes of the invalid UTF-8
+        // sequences, not the bytes of their replacement
+        let input: &[u8] = b"x\xe9 ipsum\n\xff\xfe\xe9 ipsum \xc3\xa9 ipsum\n";
+        let of = read_arguments(vec!["grep", "-b", "-o", "-e", "ipsum"]);
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(
+            "3: ipsum\n13: ipsum\n22: ipsum\n",
+            String::from_utf8(output).unwrap()
+        );
+        assert_eq!(0, raw_offset(b"\xe9x", 0));
+        assert_eq!(1, raw_offset(b"\xe9x", 3));
+        assert_eq!(3, raw_offset(b"ab\xe9", 5));
+    }
+
+    #[test]
+    fn ts_print_matches_context_groups() {
+        let input: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let re = Regex::new("line (2|4|9)$").unwrap();
+
+        // the overlapping windows of 2 and 4 are merged, 9 is a new group
+        let of = read_arguments(vec!["grep", "-n", "-C", "1", "-e", "line (2|4|9)$"]);
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(&input), "f1", &re, &of).unwrap();
+        assert_eq!(3, count);
+        assert_eq!(
+            "1- line 1\n2: line 2\n3- line 3\n4: line 4\n5- line 5\n--\n\
+             8- line 8\n9: line 9\n10- line 10\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        // the trailing context of the last match with -m
+        let of = read_arguments(vec!["grep", "-m", "1", "-A", "2", "-e", "x"]);
+        let lines = match_lines_with_context(io::Cursor::new(&input), &re, &of).collect::<Vec<_>>();
+        let numbers: Vec<usize> = lines.iter().map(|line| line.0).collect();
+        assert_eq!(vec![2, 3, 4], numbers);
+
+        // -c ignores the context
+        let of = read_arguments(vec!["grep", "-c", "-C", "1", "-e", "x"]);
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(&input), "f1", &re, &of).unwrap();
+        assert_eq!(3, count);
+    }
+
+    #[test]
+    fn ts_search_inputs_count_per_file() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\ndolor\n");
+        let f2 = write_file(&dir, "f2", b"sit\namet\n");
+
+        let of = read_arguments(vec!["grep", "-c", "-e", "o", &f1, &f2]);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let summary = search_inputs(&mut output, &of.inputs, &re, &of).unwrap();
+        assert_eq!(2, summary.line_count);
+        assert_eq!(
+            format!("{}:2\n{}:0\n", f1, f2),
+            String::from_utf8(output).unwrap()
+        );
+
+        // -v counts the lines that do not match
+        let of = read_arguments(vec!["grep", "-c", "-v", "-e", "o", &f1, &f2]);
+        let mut output: Vec<u8> = Vec::new();
+        search_inputs(&mut output, &of.inputs, &re, &of).unwrap();
+        assert_eq!(
+            format!("{}:1\n{}:2\n", f1, f2),
+            String::from_utf8(output).unwrap()
+        );
+
+        // a single input is not prefixed
+        let of = read_arguments(vec!["grep", "-c", "-v", "-e", "o", &f2]);
+        let mut output: Vec<u8> = Vec::new();
+        search_inputs(&mut output, &of.inputs, &re, &of).unwrap();
+        assert_eq!("2\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn ts_search_inputs_file_name_prefix() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\n");
+        let f2 = write_file(&dir, "f2", b"dolor ipsum\n");
+        let re = Regex::new("ipsum").unwrap();
+        let search = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            let mut output: Vec<u8> = Vec::new();
+            search_inputs(&mut output, &of.inputs, &re, &of).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // one file is not prefixed unless -H is given
+        assert_eq!("ipsum\n", search(vec!["grep", "-e", "ipsum", &f1]));
+        assert_eq!(
+            format!("{}:ipsum\n", f1),
+            search(vec!["grep", "-H", "-e", "ipsum", &f1])
+        );
+
+        // two files are prefixed
+        assert_eq!(
+            format!("{}:ipsum\n{}:dolor ipsum\n", f1, f2),
+            search(vec!["grep", "-e", "ipsum", &f1, &f2])
+        );
+        assert_eq!(
+            format!("{}:2: ipsum\n{}:1: dolor ipsum\n", f1, f2),
+            search(vec!["grep", "-n", "-e", "ipsum", &f1, &f2])
+        );
+
+        // -h removes the prefix
+        assert_eq!(
+            "ipsum\ndolor ipsum\n",
+            search(vec!["grep", "-h", "-e", "ipsum", &f1, &f2])
+        );
+        assert_eq!(
+            "ipsum\ndolor ipsum\n",
+            search(vec!["grep", "--no-filename", "-e", "ipsum", &f1, &f2])
+        );
+        let args = vec!["grep", "-h", "-H", "-e", "ipsum", "f1"];
+        assert!(build_app().get_matches_from_safe(args).is_err());
+
+        // the standard input is not prefixed
+        assert!(!read_arguments(vec!["grep", "-e", "ipsum"]).with_file_name);
+    }
+
+    #[test]
+    fn ts_search_inputs_heading() {
+        let dir = TempDir::new().unwrap();
+        let f1 = write_file(&dir, "f1", b"lorem\nipsum\ndolor\n");
+        let f2 = write_file(&dir, "f2", b"sit\namet\n");
+        let f3 = write_file(&dir, "f3", b"consectetur\nlorem\n");
+        let inputs = vec![f1.clone(), f2, f3.clone()];
+
+        // the files without matches are not listed
+        let of = read_arguments(vec!["grep", "--heading", "-H", "-n", "-e", "lorem|dolor"]);
+        assert!(of.heading);
+        let re = build_regex(&of).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let summary = search_inputs(&mut output, &inputs, &re, &of).unwrap();
+        assert_eq!(3, summary.line_count);
+        assert_eq!(
+            format!("{}\n1: lorem\n3: dolor\n\n{}\n2: lorem\n", f1, f3),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn ts_count_as_exit() {
+        let of = read_arguments(vec!["grep", "--count-as-exit", "-e", "o"]);
+        assert!(of.count_as_exit);
+        let re = build_regex(&of).unwrap();
+        let input = b"lorem\nipsum\ndolor\nsit\namet\n";
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(2, count_exit_code(count));
+
+        assert_eq!(0, count_exit_code(0));
+        assert_eq!(255, count_exit_code(255));
+        assert_eq!(255, count_exit_code(1000));
+    }
+
+    /// Returns the contents compressed with gzip.
+    fn gzip(contents: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn ts_open_input_decompress() {
+        let dir = TempDir::new().unwrap();
+        let contents = b"lorem\nipsum dolor\nsit amet\n";
+        let compressed = write_file(&dir, "log.gz", &gzip(contents));
+        // detected by the magic bytes
+        let no_extension = write_file(&dir, "log", &gzip(contents));
+        let plain = write_file(&dir, "plain.txt", contents);
+        let re = Regex::new("dolor").unwrap();
+
+        let of = read_arguments(vec!["grep", "--decompress", "-e", "dolor"]);
+        assert!(of.decompress);
+        for input in [&compressed, &no_extension, &plain].iter() {
+            let reader = open_input(input, &of).unwrap();
+            let lines = match_lines(reader, &re, false, None, b'\n').collect::<Vec<_>>();
+            assert_eq!(vec![(2, String::from("ipsum dolor"))], lines);
         }
 
-        line_count += lines.len();
-        if output_formatter.only_line_count {
-            continue;
+        // without --decompress the compressed data is searched as it is
+        let reader = open_input(&compressed, &OutputFormatter::new("dolor")).unwrap();
+        assert!(is_gzip(&mut BufReader::new(reader)));
+    }
+
+    #[test]
+    fn ts_open_input_pre() {
+        let dir = TempDir::new().unwrap();
+        let file = write_file(&dir, "f1", b"lorem\nipsum dolor\nsit amet\n");
+        let re = Regex::new("dolor|amet").unwrap();
+
+        // a trivial filter gives the same results
+        let of = read_arguments(vec!["grep", "--pre", "cat", "-e", "dolor"]);
+        assert_eq!(Some(String::from("cat")), of.pre);
+        let filtered = match_lines(open_input(&file, &of).unwrap(), &re, false, None, b'\n')
+            .collect::<Vec<_>>();
+        let plain = OutputFormatter::new("dolor");
+        let direct = match_lines(open_input(&file, &plain).unwrap(), &re, false, None, b'\n')
+            .collect::<Vec<_>>();
+        assert_eq!(direct, filtered);
+        assert_eq!(2, filtered.len());
+
+        // the failures of the command are reported for the file
+        let of = read_arguments(vec!["grep", "--pre", "false", "-e", "dolor"]);
+        match open_input(&file, &of) {
+            Ok(_) => panic!(),
+            Err(err) => assert_eq!(ErrCode::ErrorCannotOpenFileForReading, err),
         }
+        let of = read_arguments(vec!["grep", "--pre", "/nonexistent/filter", "-e", "dolor"]);
+        assert!(open_input(&file, &of).is_err());
+    }
 
-        for line in lines {
-            println!(
-                "{}{}{}",
-                if output_formatter.with_file_name {
-                    format!("{} ", current_file)
-                } else {
-                    format!("")
-                },
-                if output_formatter.has_line_numbers {
-                    format!("{}: ", line.0)
-                } else {
-                    format!("")
-                },
-                line.1
+    #[test]
+    fn ts_report_encoding_issues() {
+        let dir = TempDir::new().unwrap();
+        let utf8 = write_file(&dir, "utf8", "caña\nniño\n".as_bytes());
+        let latin1 = write_file(&dir, "latin1", b"ca\xf1a\nni\xf1o\n");
+        let of = read_arguments(vec!["grep", "--report-encoding-issues", "-e", "ni"]);
+        assert!(of.report_encoding_issues);
+        let re = build_regex(&of).unwrap();
+
+        let mut issues: Vec<String> = Vec::new();
+        for input in [&utf8, &latin1].iter() {
+            let reader = Utf8Tracker::new(open_input(input, &of).unwrap());
+            let invalid_utf8 = Rc::clone(&reader.invalid_utf8);
+            let mut output: Vec<u8> = Vec::new();
+            // the search is not affected
+            assert_eq!(
+                1,
+                print_matches(&mut output, reader, input, &re, &of).unwrap()
             );
+            if invalid_utf8.get() {
+                issues.push(input.to_string());
+            }
         }
+        assert_eq!(
+            vec![format!("grep: {}: invalid UTF-8 sequences", latin1)],
+            format_encoding_report(&issues)
+        );
     }
 
-    if output_formatter.only_line_count {
-        println!("{}", line_count);
+    #[test]
+    fn ts_utf8_tracker_split_characters() {
+        // a multibyte character split between two buffers is valid
+        let reader = io::BufReader::with_capacity(3, "aañ€b".as_bytes());
+        let mut tracker = Utf8Tracker::new(reader);
+        let mut contents = String::new();
+        tracker.read_to_string(&mut contents).unwrap();
+        assert_eq!("aañ€b", contents);
+        assert!(!tracker.invalid_utf8.get());
+
+        let reader = io::BufReader::with_capacity(3, &b"aa\xc3b\n"[..]);
+        let tracker = Utf8Tracker::new(reader);
+        let invalid_utf8 = Rc::clone(&tracker.invalid_utf8);
+        assert_eq!(
+            vec!["aa\u{fffd}b"],
+            read_lines(tracker, b'\n').collect::<Vec<String>>()
+        );
+        assert!(invalid_utf8.get());
     }
-}
 
-#[cfg(test)]
-mod grep_ts {
-    use super::*;
-    use std::io;
+    #[test]
+    fn ts_expand_inputs_files_and_directories() {
+        let dir = TempDir::new().unwrap();
+        let file = write_file(&dir, "f1", b"lorem\n");
+        std::fs::create_dir_all(dir.path().join("d1/d2")).unwrap();
+        let nested_1 = write_file(&dir, "d1/n1", b"ipsum\n");
+        let nested_2 = write_file(&dir, "d1/d2/n2", b"dolor\n");
+        let directory = dir.path().join("d1").to_str().unwrap().to_string();
+
+        let inputs = vec![file.clone(), directory.clone()];
+
+        // with -r the file is searched directly and the directory recursively
+        let mut files = expand_inputs(&inputs, true, false, &NameFilter::default());
+        files.sort();
+        let mut expected = vec![file.clone(), nested_1, nested_2];
+        expected.sort();
+        assert_eq!(expected, files);
+
+        // without -r the arguments are not expanded
+        assert_eq!(
+            inputs,
+            expand_inputs(&inputs, false, true, &NameFilter::default())
+        );
+
+        // stdin is never expanded
+        assert_eq!(
+            vec!["-"],
+            expand_inputs(&[String::from("-")], true, true, &NameFilter::default())
+        );
+    }
 
     #[test]
-    fn ts_output_formatter_new() {
-        let pattern = "the pattern";
-        let of = OutputFormatter::new(pattern);
-        assert_eq!(false, of.ignore_match);
-        assert_eq!(false, of.has_line_numbers);
-        assert_eq!(false, of.with_file_name);
-        assert_eq!(false, of.only_file_names);
-        assert_eq!(false, of.only_line_count);
-        assert_eq!(pattern, of.pattern);
-        assert_eq!(0usize, of.inputs.len());
+    fn ts_glob_match() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(glob_match("*.rs", ".rs"));
+        assert!(!glob_match("*.rs", "main.rs.bak"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*.[ch]", "lib.h"));
+        assert!(!glob_match("*.[ch]", "lib.o"));
+        assert!(glob_match("f[0-9]", "f7"));
+        assert!(glob_match("f[!0-9]", "fx"));
+        assert!(!glob_match("f[^0-9]", "f7"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("*b*", "abc"));
+        assert!(glob_match("ñ?", "ñé"));
     }
 
     #[test]
-    fn ts_read_arguments() {
-        let pattern = "the pattern";
-        // load the pattern and stdin
-        let of = read_arguments(vec!["grep", "-e", "the pattern"]);
+    fn ts_expand_inputs_include_exclude() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("src/bin")).unwrap();
+        let lib = write_file(&dir, "src/lib.rs", b"lorem\n");
+        let main = write_file(&dir, "src/bin/main.rs", b"lorem\n");
+        let test = write_file(&dir, "src/bin/test_main.rs", b"lorem\n");
+        let notes = write_file(&dir, "src/notes.txt", b"lorem\n");
+        let readme = write_file(&dir, "src/README.md", b"lorem\n");
+        let root = dir.path().join("src").to_str().unwrap().to_string();
 
-        assert_eq!(false, of.ignore_match);
-        assert_eq!(false, of.has_line_numbers);
-        assert_eq!(false, of.with_file_name);
-        assert_eq!(false, of.only_file_names);
-        assert_eq!(false, of.only_line_count);
-        assert_eq!(pattern, of.pattern);
-        assert_eq!(1usize, of.inputs.len());
-        assert_eq!("-", of.inputs[0]);
+        let expand = |args: Vec<&str>| {
+            let of = read_arguments(args);
+            expand_inputs(
+                std::slice::from_ref(&root),
+                of.recursive,
+                of.sort_paths,
+                &of.name_filter,
+            )
+        };
 
-        // load input files
-        let of = read_arguments(vec!["grep", "-e", "the pattern", "f1", "f2", "f3"]);
+        assert_eq!(
+            vec![main.clone(), test.clone(), lib.clone()],
+            expand(vec!["grep", "-r", "--include", "*.rs", "-e", "lorem"])
+        );
+        // the globs of several options are combined
+        assert_eq!(
+            vec![readme, main.clone(), lib.clone()],
+            expand(vec![
+                "grep",
+                "-r",
+                "--include=*.rs",
+                "--include=*.md",
+                "--exclude",
+                "test_*",
+                "-e",
+                "lorem"
+            ])
+        );
+        assert_eq!(
+            vec![main, test, lib, notes.clone()],
+            expand(vec!["grep", "-r", "--exclude=*.md", "-e", "lorem"])
+        );
 
-        assert_eq!(false, of.ignore_match);
-        assert_eq!(false, of.has_line_numbers);
-        assert_eq!(false, of.with_file_name);
-        assert_eq!(false, of.only_file_names);
-        assert_eq!(false, of.only_line_count);
-        assert_eq!(pattern, of.pattern);
-        assert_eq!(3usize, of.inputs.len());
+        // the file arguments are searched even if they do not match
+        let of = read_arguments(vec!["grep", "-r", "--include=*.rs", "-e", "lorem"]);
+        assert_eq!(
+            vec![notes.clone()],
+            expand_inputs(&[notes], of.recursive, of.sort_paths, &of.name_filter)
+        );
+    }
 
-        for i in 0..of.inputs.len() {
-            assert_eq!(format!("f{}", i + 1), of.inputs[i]);
+    #[test]
+    fn ts_search_directory_tree() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("d1/d2")).unwrap();
+        write_file(&dir, "d1/n1", b"lorem\nipsum\n");
+        write_file(&dir, "d1/d2/n2", b"dolor\nlorem ipsum\n");
+        write_file(&dir, "d1/d2/n3", b"dolor\n");
+        let file = write_file(&dir, "f1", b"lorem\n");
+        // a symbolic link loop is not followed
+        std::os::unix::fs::symlink(dir.path().join("d1"), dir.path().join("d1/d2/loop")).unwrap();
+        let root = dir.path().join("d1").to_str().unwrap().to_string();
+
+        let of = read_arguments(vec!["grep", "-r", "-e", "lorem"]);
+        assert!(of.with_file_name);
+        let inputs = expand_inputs(
+            std::slice::from_ref(&root),
+            of.recursive,
+            of.sort_paths,
+            &of.name_filter,
+        );
+        let mut output: Vec<u8> = Vec::new();
+        let summary =
+            search_inputs(&mut output, &inputs, &Regex::new("lorem").unwrap(), &of).unwrap();
+        assert_eq!(2, summary.line_count);
+        assert!(!summary.failed);
+        assert_eq!(
+            format!("{0}/d2/n2:lorem ipsum\n{0}/n1:lorem\n", root),
+            String::from_utf8(output).unwrap()
+        );
+
+        // without -r the directory is reported and the other inputs searched
+        let of = read_arguments(vec!["grep", "-e", "lorem"]);
+        let inputs = expand_inputs(
+            &[root.clone(), file],
+            of.recursive,
+            of.sort_paths,
+            &of.name_filter,
+        );
+        let mut output: Vec<u8> = Vec::new();
+        let summary =
+            search_inputs(&mut output, &inputs, &Regex::new("lorem").unwrap(), &of).unwrap();
+        assert_eq!(1, summary.line_count);
+        assert!(summary.failed);
+        assert_eq!("lorem\n", String::from_utf8(output).unwrap());
+        match open_input(&root, &of) {
+            Ok(_) => panic!(),
+            Err(err) => assert_eq!(ErrCode::ErrorInputIsADirectory, err),
         }
+    }
 
-        // check all flags
-        let of = read_arguments(vec![
-            "grep",
-            "-v",
-            "-n",
-            "-l",
-            "-c",
-            "-H",
-            "-e",
-            "the pattern",
-            "f1",
-            "f2",
-            "f3",
-        ]);
+    #[test]
+    fn ts_expand_inputs_sorted_by_path() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("b/a")).unwrap();
+        std::fs::create_dir_all(dir.path().join("a")).unwrap();
+        let names = ["c", "b/z", "a/y", "b/a/x", "a1", "b/b"];
+        let files: Vec<String> = names
+            .iter()
+            .map(|name| write_file(&dir, name, b"lorem\n"))
+            .collect();
+        let root = dir.path().to_str().unwrap().to_string();
 
-        assert_eq!(true, of.ignore_match);
-        assert_eq!(true, of.has_line_numbers);
-        assert_eq!(true, of.with_file_name);
-        assert_eq!(true, of.only_file_names);
-        assert_eq!(true, of.only_line_count);
-        assert_eq!(pattern, of.pattern);
-        assert_eq!(3usize, of.inputs.len());
+        let of = read_arguments(vec!["grep", "-r", "-e", "lorem"]);
+        assert!(of.sort_paths);
+        let expected = vec![
+            files[2].clone(),
+            files[4].clone(),
+            files[3].clone(),
+            files[5].clone(),
+            files[1].clone(),
+            files[0].clone(),
+        ];
+        assert_eq!(
+            expected,
+            expand_inputs(
+                std::slice::from_ref(&root),
+                true,
+                of.sort_paths,
+                &of.name_filter
+            )
+        );
 
-        for i in 0..of.inputs.len() {
-            assert_eq!(format!("f{}", i + 1), of.inputs[i]);
-        }
+        let of = read_arguments(vec!["grep", "-r", "--sort=none", "-e", "lorem"]);
+        assert!(!of.sort_paths);
+        assert_eq!(
+            6,
+            expand_inputs(&[root], true, of.sort_paths, &of.name_filter).len()
+        );
     }
 
     #[test]
@@ -424,11 +3920,49 @@ mod grep_ts {
 
         // regext matches, don't ignore match
         let reader = io::Cursor::new(b"lorem\nipsum\r\ndolor");
-        assert_eq!(true, find_match(reader, &re, dont_ignore_match).unwrap());
+        assert_eq!(
+            true,
+            find_match(reader, &re, dont_ignore_match, b'\n').unwrap()
+        );
 
-        // regex matches and but ignore match
+        // regex matches but ignore match, the other lines do not match
         let reader = io::Cursor::new(b"lorem\nipsum\r\ndolor");
-        assert_eq!(false, find_match(reader, &re, ignore_match).unwrap());
+        assert_eq!(true, find_match(reader, &re, ignore_match, b'\n').unwrap());
+
+        // every line matches and ignore match
+        let reader = io::Cursor::new(b"lorem\nlorem ipsum");
+        assert_eq!(false, find_match(reader, &re, ignore_match, b'\n').unwrap());
+    }
+
+    #[test]
+    fn ts_invalid_utf8_input() {
+        let re = Regex::new("ipsum").unwrap();
+        let input: &[u8] = b"lorem \xff\nipsum \xff\xfe dolor\nsit\n";
+
+        assert!(find_match(io::Cursor::new(input), &re, false, b'\n').unwrap());
+        let lines =
+            match_lines(io::Cursor::new(input), &re, false, None, b'\n').collect::<Vec<_>>();
+        assert_eq!(
+            vec![(2, String::from("ipsum \u{fffd}\u{fffd} dolor"))],
+            lines
+        );
+
+        // the lines after the invalid bytes are still searched
+        let re = Regex::new("sit").unwrap();
+        let of = OutputFormatter::new("sit");
+        let mut output: Vec<u8> = Vec::new();
+        let count = print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(1, count);
+        assert_eq!("sit\n", String::from_utf8(output).unwrap());
+
+        // binary files are reported instead of printed
+        let input: &[u8] = b"\x00\xff sit\n";
+        let mut output: Vec<u8> = Vec::new();
+        print_matches(&mut output, io::Cursor::new(input), "f1", &re, &of).unwrap();
+        assert_eq!(
+            "Binary file f1 matches\n",
+            String::from_utf8(output).unwrap()
+        );
     }
 
     #[test]
@@ -439,11 +3973,14 @@ mod grep_ts {
 
         // regex does not match
         let reader = io::Cursor::new(b"lorem\nipsum\r\ndolor");
-        assert_eq!(false, find_match(reader, &re, dont_ignore_match).unwrap());
+        assert_eq!(
+            false,
+            find_match(reader, &re, dont_ignore_match, b'\n').unwrap()
+        );
 
         // regex does not match and ignore match
         let reader = io::Cursor::new(b"lorem\nipsum\r\ndolor");
-        assert_eq!(true, find_match(reader, &re, ignore_match).unwrap());
+        assert_eq!(true, find_match(reader, &re, ignore_match, b'\n').unwrap());
     }
 
     #[test]
@@ -454,7 +3991,7 @@ mod grep_ts {
 
         // regext matches, don't ignore match
         let reader = io::Cursor::new(b"lorem\nipsum is second line\r\ndolor");
-        let m = match_lines(reader, &re, dont_ignore_match).unwrap();
+        let m = match_lines(reader, &re, dont_ignore_match, None, b'\n').collect::<Vec<_>>();
 
         assert_eq!(1usize, m.len());
         assert_eq!(2, m[0].0);
@@ -462,7 +3999,7 @@ mod grep_ts {
 
         // regext matches, but ignore match
         let reader = io::Cursor::new(b"lorem\nipsum is sencond line\r\ndolor");
-        let m = match_lines(reader, &re, ignore_match).unwrap();
+        let m = match_lines(reader, &re, ignore_match, None, b'\n').collect::<Vec<_>>();
 
         assert_eq!(2usize, m.len());
         assert_eq!(1, m[0].0);
@@ -479,13 +4016,13 @@ mod grep_ts {
 
         // regext does not match
         let reader = io::Cursor::new(b"lorem\nipsum is second line\r\ndolor");
-        let m = match_lines(reader, &re, dont_ignore_match).unwrap();
+        let m = match_lines(reader, &re, dont_ignore_match, None, b'\n').collect::<Vec<_>>();
 
         assert_eq!(0usize, m.len());
 
         // regext does not match but ignore
         let reader = io::Cursor::new(b"lorem\nipsum is second line\r\ndolor");
-        let m = match_lines(reader, &re, ignore_match).unwrap();
+        let m = match_lines(reader, &re, ignore_match, None, b'\n').collect::<Vec<_>>();
 
         assert_eq!(3usize, m.len());
     }
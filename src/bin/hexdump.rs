@@ -1,16 +1,34 @@
 use clap::{App, Arg};
+use std::convert::TryFrom;
 use std::ffi::OsString;
 use std::fs;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::process::{self, Command, Stdio};
 ///
 /// A clone of hexdump
 ///
 use std::{cmp, env};
 
 extern crate toolslib;
-use crate::toolslib::ErrCode;
+use crate::toolslib::{parse_size, read_all, ErrCode};
 
 const VERSION: &str = "ver. 0.0.2";
 
+/// Width of the bar of the most frequent byte value in `--histogram --bars`
+const HISTOGRAM_BAR_WIDTH: usize = 50;
+
+/// The dump layouts of other tools selected with `--layout`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    /// `xxd`: 8 digit offset, 2 byte groups in file order and the ASCII text
+    Xxd,
+    /// `od`: octal offset and 2 byte little endian words in octal
+    Od,
+    /// `hexdump`: hexadecimal offset and 2 byte little endian words
+    Hexdump,
+}
+
 #[derive(Debug)]
 struct CommandLineOptions {
     one_byte_octal: bool,
@@ -19,6 +37,21 @@ struct CommandLineOptions {
     two_bytes_hex: bool,
     two_bytes_decimal: bool,
     two_bytes_octal: bool,
+    ascii_only: bool,
+    min_length: usize,
+    pager: bool,
+    verify_file: Option<String>,
+    diff_file: Option<String>,
+    shift: usize,
+    json: bool,
+    histogram: bool,
+    bars: bool,
+    record_size: Option<usize>,
+    find: Option<Vec<u8>>,
+    no_final_offset: bool,
+    plain: bool,
+    only_offsets: bool,
+    layout: Option<Layout>,
     length_bytes: i32,
     offset: i32,
     input_file: String,
@@ -33,6 +66,21 @@ impl CommandLineOptions {
             two_bytes_hex: true,
             two_bytes_decimal: false,
             two_bytes_octal: false,
+            ascii_only: false,
+            min_length: 4,
+            pager: false,
+            verify_file: None,
+            diff_file: None,
+            shift: 0,
+            json: false,
+            histogram: false,
+            bars: false,
+            record_size: None,
+            find: None,
+            no_final_offset: false,
+            plain: false,
+            only_offsets: false,
+            layout: None,
             length_bytes: 0,
             offset: 0,
             input_file: String::from(""),
@@ -46,7 +94,7 @@ where
     T: Into<OsString> + Clone,
 {
     let mut cmd_options = CommandLineOptions::new();
-    let matches: _ = App::new("hexdump: hexdump clone command written in Rust")
+    let matches = App::new("hexdump: hexdump clone command written in Rust")
         .version(VERSION)
         .author("Manuel Berrocal")
         .about("Display file contects in hexadecimal, decimal, orctal or ASCII")
@@ -134,13 +182,126 @@ where
                 ])
                 .help("Two bytes octal display."),
         )
+        .arg(
+            Arg::with_name("ascii_only")
+                .long("ascii-only")
+                .takes_value(false)
+                .help("Display only runs of printable ASCII characters and their offsets."),
+        )
+        .arg(
+            Arg::with_name("min_length")
+                .long("min-length")
+                .takes_value(true)
+                .multiple(false)
+                .requires("ascii_only")
+                .help("Minimum length of the ASCII runs displayed by --ascii-only (default 4)."),
+        )
+        .arg(
+            Arg::with_name("pager")
+                .long("pager")
+                .takes_value(false)
+                .help("Page the output with $PAGER when it does not fit in the terminal."),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .takes_value(true)
+                .value_name("DUMPFILE")
+                .help("Verify that the canonical dump in DUMPFILE matches the input file."),
+        )
+        .arg(
+            Arg::with_name("diff")
+                .long("diff")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("List the offsets and values of the bytes that differ between the input file and FILE."),
+        )
+        .arg(
+            Arg::with_name("shift")
+                .long("shift")
+                .takes_value(true)
+                .value_name("N")
+                .requires("diff")
+                .help("Compare the byte i of the input file with the byte i+N of the --diff FILE."),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .help("Output the dump as a JSON array with the offset, bytes and ASCII of each line."),
+        )
+        .arg(
+            Arg::with_name("histogram")
+                .long("histogram")
+                .takes_value(false)
+                .help("Display the number of times each byte value appears instead of the dump."),
+        )
+        .arg(
+            Arg::with_name("bars")
+                .long("bars")
+                .takes_value(false)
+                .requires("histogram")
+                .help("Draw the --histogram counts as an ASCII bar chart."),
+        )
+        .arg(
+            Arg::with_name("record_size")
+                .long("record-size")
+                .takes_value(true)
+                .value_name("N")
+                .help("Group the output in records of N bytes separated by a blank line."),
+        )
+        .arg(
+            Arg::with_name("find")
+                .long("find")
+                .takes_value(true)
+                .value_name("SIGNATURE")
+                .help("Start the dump at the first occurrence of the hexadecimal SIGNATURE, like 504b0304."),
+        )
+        .arg(
+            Arg::with_name("no_final_offset")
+                .long("no-final-offset")
+                .takes_value(false)
+                .help("Do not print the last line with the offset of the end of the input."),
+        )
+        .arg(
+            Arg::with_name("plain")
+                .long("plain")
+                .takes_value(false)
+                .conflicts_with("only_offsets")
+                .help("Do not print the offset column, nor the final offset line."),
+        )
+        .arg(
+            Arg::with_name("only_offsets")
+                .long("only-offsets")
+                .takes_value(false)
+                .help("Print only the offset column of each line."),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .takes_value(true)
+                .value_name("TOOL")
+                .possible_values(&["xxd", "od", "hexdump"])
+                .conflicts_with_all(&[
+                    "one_byte_octal",
+                    "one_byte_char",
+                    "cannonical",
+                    "two_bytes_hex",
+                    "two_bytes_decimal",
+                    "two_bytes_octal",
+                    "record_size",
+                    "plain",
+                    "only_offsets",
+                ])
+                .help("Mimic the default output of xxd, od or hexdump, to compare the dumps."),
+        )
         .arg(
             Arg::with_name("length")
                 .short("n")
                 .long("length")
                 .takes_value(true)
                 .multiple(false)
-                .help("Number of input bytes to interpret."),
+                .help("Number of input bytes to interpret, accepts 0x prefixes and k, m, g suffixes."),
         )
         .arg(
             Arg::with_name("offset")
@@ -148,7 +309,7 @@ where
                 .long("skip")
                 .takes_value(true)
                 .multiple(false)
-                .help("Skip offset."),
+                .help("Skip offset, accepts 0x prefixes and k, m, g suffixes."),
         )
         .arg(
             Arg::with_name("file_name")
@@ -164,21 +325,109 @@ where
     }
 
     if let Some(i) = matches.value_of("length") {
-        if let Ok(i) = i.parse::<i32>() {
-            cmd_options.length_bytes = i.abs()
-        } else {
-            eprintln!("Length bytes takes only integer arguments");
-            return Err(ErrCode::ErrorArgumentParsing);
+        match i32::try_from(parse_size(i)?) {
+            Ok(i) => cmd_options.length_bytes = i,
+            Err(_) => {
+                eprintln!("Length bytes is too large");
+                return Err(ErrCode::ErrorArgumentParsing);
+            }
         }
     }
 
     if let Some(i) = matches.value_of("offset") {
-        match i.parse::<i32>() {
-            Ok(i) => cmd_options.offset = i.abs(),
+        match i32::try_from(parse_size(i)?) {
+            Ok(i) => cmd_options.offset = i,
+            Err(_) => {
+                eprintln!("Skip offset is too large");
+                return Err(ErrCode::ErrorArgumentParsing);
+            }
+        }
+    }
+
+    if matches.is_present("ascii_only") {
+        cmd_options.ascii_only = true;
+    }
+
+    if let Some(i) = matches.value_of("verify") {
+        cmd_options.verify_file = Some(String::from(i));
+    }
+
+    if let Some(i) = matches.value_of("diff") {
+        cmd_options.diff_file = Some(String::from(i));
+    }
+
+    if let Some(i) = matches.value_of("shift") {
+        match parse_size(i) {
+            Ok(i) => cmd_options.shift = i as usize,
             Err(_) => return Err(ErrCode::ErrorArgumentParsing),
         }
     }
 
+    if matches.is_present("pager") {
+        cmd_options.pager = true;
+    }
+
+    if matches.is_present("json") {
+        cmd_options.json = true;
+    }
+
+    if matches.is_present("histogram") {
+        cmd_options.histogram = true;
+    }
+
+    if matches.is_present("bars") {
+        cmd_options.bars = true;
+    }
+
+    if let Some(i) = matches.value_of("record_size") {
+        match i.parse::<usize>() {
+            Ok(i) if i > 0 => cmd_options.record_size = Some(i),
+            _ => {
+                eprintln!("Record size takes only positive integer arguments");
+                return Err(ErrCode::ErrorArgumentParsing);
+            }
+        }
+    }
+
+    if let Some(i) = matches.value_of("find") {
+        match parse_signature(i) {
+            Some(signature) => cmd_options.find = Some(signature),
+            None => {
+                eprintln!("The signature must be a non empty string of hexadecimal byte values");
+                return Err(ErrCode::ErrorArgumentParsing);
+            }
+        }
+    }
+
+    if matches.is_present("no_final_offset") {
+        cmd_options.no_final_offset = true;
+    }
+
+    if matches.is_present("plain") {
+        cmd_options.plain = true;
+    }
+
+    if matches.is_present("only_offsets") {
+        cmd_options.only_offsets = true;
+    }
+
+    cmd_options.layout = match matches.value_of("layout") {
+        Some("xxd") => Some(Layout::Xxd),
+        Some("od") => Some(Layout::Od),
+        Some("hexdump") => Some(Layout::Hexdump),
+        _ => None,
+    };
+
+    if let Some(i) = matches.value_of("min_length") {
+        match i.parse::<usize>() {
+            Ok(i) if i > 0 => cmd_options.min_length = i,
+            _ => {
+                eprintln!("Minimum length takes only positive integer arguments");
+                return Err(ErrCode::ErrorArgumentParsing);
+            }
+        }
+    }
+
     if matches.is_present("one_byte_octal") {
         cmd_options.two_bytes_hex = false;
         cmd_options.one_byte_octal = true;
@@ -200,11 +449,156 @@ where
     Ok(cmd_options)
 }
 
-fn get_input(input_file_name: &String) -> Result<Vec<u8>, ErrCode> {
-    match fs::read(input_file_name) {
-        Ok(b) => return Ok(b),
-        Err(_) => return Err(ErrCode::ErrorArgumentParsing),
+/**
+  Parses a byte signature written in hexadecimal, like `504b0304` or
+  `0x504b0304`. Returns None if it is empty or not a whole number of bytes.
+*/
+fn parse_signature(signature: &str) -> Option<Vec<u8>> {
+    let hex = signature.strip_prefix("0x").unwrap_or(signature);
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/**
+  Returns the index of the first occurrence of the signature in the buffer,
+  or None if the buffer does not contain it.
+*/
+fn find_signature(buf: &[u8], signature: &[u8]) -> Option<usize> {
+    buf.windows(signature.len()).position(|window| window == signature)
+}
+
+/**
+  Reads the input file starting at an offset.
+
+  - input_file_name (&String): the path of the input
+  - offset (u64): the number of bytes to skip
+
+  Regular files are seekable and the offset is reached with a seek, other
+  inputs like pipes are read and the bytes before the offset discarded.
+
+  Returns the offset of the first byte read, that is smaller than `offset`
+  when the input is shorter, and the bytes from there to the end.
+*/
+fn get_input(input_file_name: &String, offset: u64) -> Result<(usize, Vec<u8>), ErrCode> {
+    let read = |mut file: File| -> io::Result<(u64, Vec<u8>)> {
+        let start = if file.metadata()?.is_file() {
+            seek_to_offset(&mut file, offset)?
+        } else {
+            discard_to_offset(&mut file, offset)?
+        };
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok((start, buf))
+    };
+
+    match File::open(input_file_name).and_then(read) {
+        Ok((start, buf)) => Ok((start as usize, buf)),
+        Err(_) => Err(ErrCode::ErrorArgumentParsing),
+    }
+}
+
+/**
+  Moves a seekable reader to the offset, or to its end if it is shorter.
+  Returns the new position.
+*/
+fn seek_to_offset<R: Seek>(reader: &mut R, offset: u64) -> io::Result<u64> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(cmp::min(offset, len)))
+}
+
+/**
+  Reads and discards the bytes of the reader before the offset, or all of
+  them if it is shorter. Returns the number of bytes discarded.
+*/
+fn discard_to_offset<R: Read>(reader: &mut R, offset: u64) -> io::Result<u64> {
+    io::copy(&mut reader.take(offset), &mut io::sink())
+}
+
+/**
+  Reconstructs the bytes from a canonical (`-C`) dump.
+
+  - dump (&str): the text of the dump
+
+  Every line starts with the hexadecimal offset followed by the bytes in
+  hexadecimal, the ASCII gutter between `|` is ignored. The last line only
+  contains the offset of the end of the data.
+*/
+fn parse_canonical_dump(dump: &str) -> Result<Vec<u8>, ErrCode> {
+    let mut buf: Vec<u8> = Vec::new();
+
+    for line in dump.lines() {
+        let hex = match line.find('|') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut tokens = hex.split_whitespace();
+        let offset = match tokens.next() {
+            Some(token) => match usize::from_str_radix(token, 16) {
+                Ok(offset) => offset,
+                Err(_) => return Err(ErrCode::ErrorInvalidDumpFormat),
+            },
+            None => continue,
+        };
+        if buf.len() < offset {
+            buf.resize(offset, 0);
+        }
+        buf.truncate(offset);
+
+        for token in tokens {
+            match u8::from_str_radix(token, 16) {
+                Ok(byte) if token.len() == 2 => buf.push(byte),
+                _ => return Err(ErrCode::ErrorInvalidDumpFormat),
+            }
+        }
     }
+
+    Ok(buf)
+}
+
+/**
+  Returns the offset of the first byte that differs between the buffers,
+  or None if they are identical. When one buffer is a prefix of the other
+  the offset is the length of the shortest one.
+*/
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    match a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+        Some(i) => Some(i),
+        None if a.len() != b.len() => Some(cmp::min(a.len(), b.len())),
+        None => None,
+    }
+}
+
+/**
+  Returns the bytes that differ between the buffers, the byte i of `a` is
+  compared with the byte i+shift of `b`; the first `shift` bytes of `b` are
+  ignored.
+
+  Every difference is the offset in `a` and both bytes, a byte is None when
+  the buffer is shorter.
+*/
+fn diff_bytes(a: &[u8], b: &[u8], shift: usize) -> Vec<(usize, Option<u8>, Option<u8>)> {
+    let b = if shift < b.len() { &b[shift..] } else { &[] };
+    (0..cmp::max(a.len(), b.len()))
+        .map(|i| (i, a.get(i).copied(), b.get(i).copied()))
+        .filter(|(_, x, y)| x != y)
+        .collect()
+}
+
+/**
+  Formats a difference returned by `diff_bytes`: the offset and both bytes
+  in hexadecimal, `--` for the missing bytes.
+*/
+fn format_difference(difference: &(usize, Option<u8>, Option<u8>)) -> String {
+    let byte = |b: Option<u8>| match b {
+        Some(b) => format!("{:02x}", b),
+        None => String::from("--"),
+    };
+    format!("{:07x} {} {}", difference.0, byte(difference.1), byte(difference.2))
 }
 
 /**
@@ -214,6 +608,8 @@ fn get_input(input_file_name: &String) -> Result<Vec<u8>, ErrCode> {
 #[derive(Debug)]
 struct Formatter {
     buf: Vec<u8>,
+    /* offset in the input of the first byte of buf */
+    base: usize,
     cannonical: bool,
     one_byte_output: bool,
     two_byte_output: bool,
@@ -223,12 +619,29 @@ struct Formatter {
     oct_output: bool,
     offset: usize,
     bytes_per_line: usize,
+    record_size: Option<usize>,
+    pending_separator: bool,
+    /* the last line is the offset of the end of the input */
+    final_offset: bool,
+    /* the lines do not start with the offset */
+    plain: bool,
+    /* the lines are only the offset */
+    only_offsets: bool,
+    /* the lines mimic the dump of another tool */
+    layout: Option<Layout>,
 }
 
 impl Formatter {
-    fn new(buf: Vec<u8>, cmd_options: &CommandLineOptions) -> Formatter {
+    /**
+      Creates a formatter for the bytes of the input starting at `base`,
+      the dump starts at the `-s` offset of the input, or at its end if the
+      input is shorter.
+    */
+    fn new(buf: Vec<u8>, base: usize, cmd_options: &CommandLineOptions) -> Formatter {
+        let offset = cmp::min((cmd_options.offset as usize).saturating_sub(base), buf.len());
         let mut fmt = Formatter {
-            buf: buf,
+            buf,
+            base,
             cannonical: false,
             one_byte_output: false,
             two_byte_output: false,
@@ -236,8 +649,14 @@ impl Formatter {
             char_output: false,
             dec_output: false,
             oct_output: false,
-            offset: cmd_options.offset as usize,
+            offset,
             bytes_per_line: 16,
+            record_size: cmd_options.record_size,
+            pending_separator: false,
+            final_offset: !(cmd_options.no_final_offset || cmd_options.plain),
+            plain: cmd_options.plain,
+            only_offsets: cmd_options.only_offsets,
+            layout: cmd_options.layout,
         };
 
         if cmd_options.cannonical {
@@ -248,20 +667,113 @@ impl Formatter {
             fmt.two_byte_output = true;
         }
 
-        if (cmd_options.cannonical == false) & cmd_options.two_bytes_decimal {
-            fmt.dec_output;
-        } else if (cmd_options.cannonical == false)
+        if (!cmd_options.cannonical) & cmd_options.two_bytes_decimal {
+            fmt.dec_output = true;
+        } else if (!cmd_options.cannonical)
             & (cmd_options.one_byte_octal | cmd_options.two_bytes_octal)
         {
             fmt.oct_output = true;
-        } else if (cmd_options.cannonical == false) & cmd_options.two_bytes_hex {
+        } else if (!cmd_options.cannonical) & cmd_options.two_bytes_hex {
             fmt.hex_output = true;
-        } else if (cmd_options.cannonical == false) & cmd_options.one_byte_char {
+        } else if (!cmd_options.cannonical) & cmd_options.one_byte_char {
             fmt.char_output = true;
         }
 
         fmt
     }
+
+    /**
+      Returns the next line of a `--layout` dump, the lines have 16 bytes.
+      `xxd` does not print the final offset, `od` prints it in octal.
+    */
+    fn next_layout_line(&mut self, layout: Layout) -> Option<String> {
+        let address = self.base + self.offset;
+        if self.offset >= self.buf.len() {
+            if self.offset > self.buf.len() || !self.final_offset || layout == Layout::Xxd {
+                return None;
+            }
+            self.offset += 1;
+            return match layout {
+                Layout::Od => Some(format!("{:07o}", address)),
+                _ => Some(format!("{:07x}", address)),
+            };
+        }
+
+        let end = cmp::min(self.offset + self.bytes_per_line, self.buf.len());
+        let chunk = &self.buf[self.offset..end];
+        self.offset = end;
+
+        /* the words are little endian, a last odd byte is padded with 0 */
+        let words = chunk
+            .chunks(2)
+            .map(|pair| pair[0] as u16 | (*pair.get(1).unwrap_or(&0) as u16) << 8);
+
+        let line = match layout {
+            Layout::Xxd => {
+                let groups: Vec<String> = chunk
+                    .chunks(2)
+                    .map(|pair| pair.iter().map(|b| format!("{:02x}", b)).collect())
+                    .collect();
+                let ascii: String = chunk
+                    .iter()
+                    .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' })
+                    .collect();
+                format!("{:08x}: {:<39}  {}", address, groups.join(" "), ascii)
+            }
+            Layout::Od => words.fold(format!("{:07o}", address), |line, word| {
+                format!("{} {:06o}", line, word)
+            }),
+            Layout::Hexdump => words.fold(format!("{:07x}", address), |line, word| {
+                format!("{} {:04x}", line, word)
+            }),
+        };
+        Some(line)
+    }
+
+    /**
+      Returns the dump as a JSON array with one object per line,
+      `{ "offset": N, "bytes": [..], "ascii": "..." }`.
+      The ASCII representation is the one of the canonical output.
+    */
+    fn to_json(&self) -> String {
+        let start = cmp::min(self.offset, self.buf.len());
+        let records: Vec<String> = self.buf[start..]
+            .chunks(self.bytes_per_line)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let bytes: Vec<String> = chunk.iter().map(|b| b.to_string()).collect();
+                let ascii: String = (0..chunk.len())
+                    .map(|j| get_char_string_rep(&chunk[j..(j + 1)], false))
+                    .collect();
+                format!(
+                    "  {{ \"offset\": {}, \"bytes\": [{}], \"ascii\": \"{}\" }}",
+                    self.base + start + i * self.bytes_per_line,
+                    bytes.join(", "),
+                    json_escape(&ascii)
+                )
+            })
+            .collect();
+
+        if records.is_empty() {
+            String::from("[]")
+        } else {
+            format!("[\n{}\n]", records.join(",\n"))
+        }
+    }
+}
+
+/// Escapes the characters that can not appear in a JSON string.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 
@@ -274,7 +786,7 @@ impl Formatter {
 */
 fn get_char_string_rep(char_byte: &[u8], scape_control_char: bool) -> String {
     let c = String::from_utf8_lossy(char_byte);
-    if (char_byte[0] as char).is_control() {
+    if is_control_byte(char_byte[0]) {
         if scape_control_char {
             c.escape_default().to_string()
         } else {
@@ -286,16 +798,116 @@ fn get_char_string_rep(char_byte: &[u8], scape_control_char: bool) -> String {
 
 }
 
+/// Returns true if the byte is interpreted as a control character.
+fn is_control_byte(byte: u8) -> bool {
+    (byte as char).is_control()
+}
+
+/**
+  Returns the runs of printable ASCII characters found in the buffer,
+  similar to `strings(1)`.
+
+  - buf (&[u8]): the bytes to scan
+  - start (usize): offset of the first byte of `buf` in the input
+  - min_length (usize): runs shorter than this are discarded
+
+  Each run is returned together with the offset where it starts.
+*/
+fn find_ascii_runs(buf: &[u8], start: usize, min_length: usize) -> Vec<(usize, String)> {
+    let mut runs = Vec::new();
+    let mut run_start = 0usize;
+    let mut run = String::new();
+
+    for (i, byte) in buf.iter().enumerate() {
+        if byte.is_ascii() && !is_control_byte(*byte) {
+            if run.is_empty() {
+                run_start = i;
+            }
+            run.push(*byte as char);
+        } else {
+            if run.len() >= min_length {
+                runs.push((start + run_start, run.clone()));
+            }
+            run.clear();
+        }
+    }
+    if run.len() >= min_length {
+        runs.push((start + run_start, run));
+    }
+
+    runs
+}
+
+/**
+  Returns the number of times each byte value appears in the buffer,
+  indexed by the byte value.
+
+  - buf (&[u8]): the bytes to count
+*/
+fn byte_histogram(buf: &[u8]) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for byte in buf {
+        counts[*byte as usize] += 1;
+    }
+    counts
+}
+
+/**
+  Returns the lines of the histogram, one line with the count of every byte
+  value that appears, in ascending byte order.
+
+  - counts (&[u64; 256]): the counts returned by `byte_histogram`
+  - bars (bool): append a bar proportional to the count, the most frequent
+    value takes `HISTOGRAM_BAR_WIDTH` characters
+*/
+fn format_histogram(counts: &[u64; 256], bars: bool) -> Vec<String> {
+    let max = counts.iter().copied().max().unwrap_or(0);
+
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(byte, count)| {
+            let line = format!("{:02x} {:>10}", byte, count);
+            if bars {
+                let width = (*count * HISTOGRAM_BAR_WIDTH as u64).div_ceil(max);
+                format!("{} {}", line, "#".repeat(width as usize))
+            } else {
+                line
+            }
+        })
+        .collect()
+}
+
 impl Iterator for Formatter {
     type Item = String;
 
     fn next(&mut self) -> Option<String> {
         let mut output: String;
 
-        output = format!("{:07x}", self.offset);
+        if self.pending_separator {
+            self.pending_separator = false;
+            return Some(String::new());
+        }
+
+        if let Some(layout) = self.layout {
+            return self.next_layout_line(layout);
+        }
+
+        output = format!("{:07x}", self.base + self.offset);
 
         if self.offset < self.buf.len() {
-            let increment = cmp::min(self.buf.len() - self.offset, self.bytes_per_line);
+            let mut increment = cmp::min(self.buf.len() - self.offset, self.bytes_per_line);
+            let mut gutter_width = 57;
+
+            /* lines do not cross the record boundaries */
+            if let Some(record_size) = self.record_size {
+                let sub_offset = (self.base + self.offset) % record_size;
+                increment = cmp::min(increment, record_size - sub_offset);
+                output = format!("{} +{:04x}", output, sub_offset);
+                gutter_width += 6;
+            }
+
             let end = self.offset + increment;
             let mut ascci_str = String::from("");
             let mut bytes: String = String::from("");
@@ -320,10 +932,10 @@ impl Iterator for Formatter {
             } else if self.cannonical {
 
                 /* cannonical */
-                ascci_str = format!("  |");
+                ascci_str = "  |".to_string();
 
                 for i in self.offset..end {
-                    let extra_space = if i == 8 {
+                    let extra_space = if i - self.offset == 8 {
                         " "
                     } else {
                         ""
@@ -337,57 +949,188 @@ impl Iterator for Formatter {
                 ascci_str = format!("{}|", ascci_str);
             } else {
 
-                /* two bytes output */
+                /* two bytes output, little endian words like hexdump on x86,
+                   a last odd byte is the low byte of a word padded with 0 */
 
-                let rem = end - self.offset;
-                if rem < 8 {
-                    if rem % 2 == 1 {
-                        self.buf.push(0)
-                    }
-                }
+                let words = self.buf[self.offset..end].chunks(2).map(|pair| {
+                    pair[0] as u16 | (*pair.get(1).unwrap_or(&0) as u16) << 8
+                });
 
-                if self.hex_output {
-                    for i in (self.offset..end).step_by(2) {
-                        bytes = format!("{}  {:02x}{:02x}", bytes, self.buf[i+1], self.buf[i]);
-                    }
-                } else if self.oct_output {
-                    for i in (self.offset..end).step_by(2) {
-                        bytes = format!("{}  {:03}{:03}", bytes, self.buf[i+1], self.buf[i]);
-                    }
-                } else {
-                    for i in (self.offset..end).step_by(2) {
-                        bytes = format!("{}  {:05}", bytes, (self.buf[i+1] as u16)*256 + (self.buf[i] as u16));
+                for word in words {
+                    if self.hex_output {
+                        bytes = format!("{}  {:04x}", bytes, word);
+                    } else if self.oct_output {
+                        bytes = format!("{}  {:06o}", bytes, word);
+                    } else if self.dec_output {
+                        bytes = format!("{}  {:05}", bytes, word);
                     }
                 }
-                if rem % 2 == 1 { self.buf.pop(); }
             }
             self.offset += increment;
-            output = format!("{} {}", output, bytes);
+            if let Some(record_size) = self.record_size {
+                self.pending_separator = (self.base + self.offset).is_multiple_of(record_size)
+                    && self.offset < self.buf.len();
+            }
+            if self.only_offsets {
+                return Some(output);
+            }
+            if self.plain {
+                /* the bytes take the columns of the offset */
+                gutter_width -= output.len() + 2;
+                output = bytes.trim_start().to_string();
+            } else {
+                output = format!("{} {}", output, bytes);
+            }
             if self.cannonical {
-                output = format!("{:<57} {}", output, ascci_str);
+                output = format!("{:<width$} {}", output, ascci_str, width = gutter_width);
             }
             Some(output)
-        } else if self.offset == self.buf.len() {
+        } else if self.offset == self.buf.len() && self.final_offset {
             self.offset += 1;
-            Some(format!("{:07x}", self.buf.len()))
+            Some(format!("{:07x}", self.base + self.buf.len()))
         } else {
             None
         }
     }
 }
 
+/**
+  Returns true if the output has to be sent to a pager.
+
+  - is_tty (bool): standard output is a terminal
+  - terminal_height (Option<usize>): number of rows of the terminal, if known
+  - line_count (usize): number of lines of the output
+*/
+fn should_page(is_tty: bool, terminal_height: Option<usize>, line_count: usize) -> bool {
+    match terminal_height {
+        Some(height) => is_tty && line_count > height,
+        None => false,
+    }
+}
+
+/**
+  Writes the lines to the standard input of the pager in $PAGER,
+  `less` is used when the variable is not set.
+*/
+fn page_output(lines: &[String]) -> io::Result<()> {
+    let pager = env::var("PAGER").unwrap_or_else(|_| String::from("less"));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(stdin) = child.stdin.as_mut() {
+        let mut handle = io::BufWriter::new(stdin);
+        for line in lines {
+            writeln!(handle, "{}", line)?;
+        }
+        handle.flush()?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
 fn main() -> Result<(), ErrCode> {
-    let cmd_options = match read_arguments(env::args_os()) {
-        Ok(o) => o,
-        Err(e) => return Err(e),
-    };
+    let cmd_options = read_arguments(env::args_os())?;
+
+    if let Some(verify_file) = &cmd_options.verify_file {
+        let buf = read_all(&cmd_options.input_file)?;
+        let dump = match fs::read_to_string(verify_file) {
+            Ok(dump) => dump,
+            Err(_) => {
+                eprintln!("hexdump: cannot read {}", verify_file);
+                process::exit(ErrCode::ErrorCannotOpenFileForReading as i32);
+            }
+        };
+        let expected = match parse_canonical_dump(&dump) {
+            Ok(expected) => expected,
+            Err(code) => {
+                eprintln!("hexdump: {} is not a canonical dump", verify_file);
+                process::exit(code as i32);
+            }
+        };
+        match first_difference(&buf, &expected) {
+            Some(offset) => {
+                println!("{}: differs at offset {:07x}", verify_file, offset);
+                process::exit(ErrCode::ErrorVerificationFailed as i32);
+            }
+            None => {
+                println!("{}: OK", verify_file);
+                return Ok(());
+            }
+        }
+    }
 
-    let buf = match get_input(&cmd_options.input_file) {
-        Ok(b) => b,
-        Err(e) => return Err(e),
-    };
+    if let Some(diff_file) = &cmd_options.diff_file {
+        let a = read_all(&cmd_options.input_file)?;
+        let b = read_all(diff_file)?;
+        let differences = diff_bytes(&a, &b, cmd_options.shift);
+        for difference in &differences {
+            println!("{}", format_difference(difference));
+        }
+        if !differences.is_empty() {
+            process::exit(ErrCode::ErrorVerificationFailed as i32);
+        }
+        return Ok(());
+    }
+
+    let mut cmd_options = cmd_options;
+    let (base, mut buf) = get_input(&cmd_options.input_file, cmd_options.offset as u64)?;
+    let mut start = cmp::min((cmd_options.offset as usize).saturating_sub(base), buf.len());
+
+    /* the dump starts at the signature */
+    if let Some(signature) = &cmd_options.find {
+        match find_signature(&buf[start..], signature) {
+            Some(position) => {
+                start += position;
+                cmd_options.offset = (base + start) as i32;
+            }
+            None => {
+                eprintln!("hexdump: the signature is not found in {}", cmd_options.input_file);
+                process::exit(ErrCode::ErrorSignatureNotFound as i32);
+            }
+        }
+    }
+
+    if cmd_options.length_bytes > 0 {
+        buf.truncate(start + cmd_options.length_bytes as usize);
+    }
+
+    if cmd_options.ascii_only {
+        for (offset, run) in find_ascii_runs(&buf[start..], base + start, cmd_options.min_length) {
+            println!("{:07x} {}", offset, run);
+        }
+        return Ok(());
+    }
+
+    if cmd_options.histogram {
+        for line in format_histogram(&byte_histogram(&buf[start..]), cmd_options.bars) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    let fmt = Formatter::new(buf, base, &cmd_options);
+    if cmd_options.json {
+        println!("{}", fmt.to_json());
+        return Ok(());
+    }
+
+    if cmd_options.pager {
+        let lines: Vec<String> = fmt.collect();
+        let height = terminal_size::terminal_size().map(|(_, h)| h.0 as usize);
+        if should_page(io::stdout().is_terminal(), height, lines.len())
+            && page_output(&lines).is_ok()
+        {
+            return Ok(());
+        }
+        for line in lines {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
 
-    let fmt = Formatter::new(buf, &cmd_options);
     for line in fmt {
         println!("{}", line);
     }
@@ -481,6 +1224,375 @@ mod hexdump_ts {
             }
             Err(_) => assert!(false),
         }
+
+        // sizes with suffixes and hexadecimal
+        let inputs = vec!["hexdump", "-n", "1k", "-s", "0x10", "f1"];
+        match read_arguments(&inputs) {
+            Ok(cmd_options) => {
+                assert_eq!(1024, cmd_options.length_bytes);
+                assert_eq!(16, cmd_options.offset);
+            }
+            Err(_) => assert!(false),
+        }
+
+        let inputs = vec!["hexdump", "-s", "lorem", "f1"];
+        assert_eq!(true, read_arguments(&inputs).is_err());
+    }
+
+    #[test]
+    fn ts_cmd_line_read_arguments_ascii_only() {
+        let inputs = vec!["hexdump", "--ascii-only", "f1"];
+        match read_arguments(&inputs) {
+            Ok(cmd_options) => {
+                assert!(cmd_options.ascii_only);
+                assert_eq!(4, cmd_options.min_length);
+            }
+            Err(_) => panic!(),
+        }
+
+        let inputs = vec!["hexdump", "--ascii-only", "--min-length", "6", "f1"];
+        match read_arguments(&inputs) {
+            Ok(cmd_options) => {
+                assert!(cmd_options.ascii_only);
+                assert_eq!(6, cmd_options.min_length);
+            }
+            Err(_) => panic!(),
+        }
+
+        let inputs = vec!["hexdump", "--ascii-only", "--min-length", "0", "f1"];
+        assert!(read_arguments(&inputs).is_err());
+    }
+
+    #[test]
+    fn ts_verify_canonical_dump() {
+        let buf: Vec<u8> = vec![
+            66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 0x0a,
+        ];
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.cannonical = true;
+        cmd_options.two_bytes_hex = false;
+        let dump: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        let dump = dump.join("\n");
+
+        // a matching dump passes
+        let parsed = parse_canonical_dump(&dump).unwrap();
+        assert_eq!(buf, parsed);
+        assert_eq!(None, first_difference(&buf, &parsed));
+
+        // a corrupted byte reports its offset
+        let corrupted = dump.replacen(" 52 ", " 00 ", 1);
+        let parsed = parse_canonical_dump(&corrupted).unwrap();
+        assert_eq!(Some(0x10), first_difference(&buf, &parsed));
+
+        // a truncated dump differs at the end of the dump data
+        let parsed = parse_canonical_dump(dump.lines().next().unwrap()).unwrap();
+        assert_eq!(Some(0x10), first_difference(&buf, &parsed));
+
+        // not a dump
+        assert!(parse_canonical_dump("lorem ipsum").is_err());
+    }
+
+    #[test]
+    fn ts_formatter_to_json() {
+        let mut buf: Vec<u8> = vec![66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81];
+        buf.extend_from_slice(&[0x22, 0x0a]);
+        let fmt = Formatter::new(buf, 0, &CommandLineOptions::new());
+
+        let expected = [
+            "[",
+            "  { \"offset\": 0, \"bytes\": [66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81], \"ascii\": \"BCDEFGHIJKLMNOPQ\" },",
+            "  { \"offset\": 16, \"bytes\": [34, 10], \"ascii\": \"\\\".\" }",
+            "]",
+        ];
+        assert_eq!(expected.join("\n"), fmt.to_json());
+
+        // empty input
+        let fmt = Formatter::new(Vec::new(), 0, &CommandLineOptions::new());
+        assert_eq!("[]", fmt.to_json());
+    }
+
+    #[test]
+    fn ts_byte_histogram() {
+        let counts = byte_histogram(&[0x41, 0x00, 0x41, 0xff, 0x41, 0x00]);
+        assert_eq!(3, counts[0x41]);
+        assert_eq!(2, counts[0x00]);
+        assert_eq!(1, counts[0xff]);
+        assert_eq!(6, counts.iter().sum::<u64>());
+
+        let lines = format_histogram(&counts, false);
+        assert_eq!(vec!["00          2", "41          3", "ff          1"], lines);
+
+        // the bars are proportional to the most frequent value
+        let lines = format_histogram(&counts, true);
+        assert_eq!(format!("41          3 {}", "#".repeat(HISTOGRAM_BAR_WIDTH)), lines[1]);
+        assert_eq!(format!("ff          1 {}", "#".repeat(17)), lines[2]);
+
+        // empty input
+        assert_eq!(0, format_histogram(&byte_histogram(&[]), true).len());
+
+        let inputs = vec!["hexdump", "--histogram", "--bars", "f1"];
+        match read_arguments(&inputs) {
+            Ok(cmd_options) => {
+                assert!(cmd_options.histogram);
+                assert!(cmd_options.bars);
+            }
+            Err(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn ts_formatter_record_size() {
+        let buf: Vec<u8> = (0x41..0x4d).collect();
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.record_size = Some(4);
+        let lines: Vec<String> = Formatter::new(buf, 0, &cmd_options).collect();
+
+        assert_eq!(6, lines.len());
+        assert_eq!("0000000 +0000   4241  4443", lines[0]);
+        assert_eq!("", lines[1]);
+        assert_eq!("0000004 +0000   4645  4847", lines[2]);
+        assert_eq!("", lines[3]);
+        assert_eq!("0000008 +0000   4a49  4c4b", lines[4]);
+        assert_eq!("000000c", lines[5]);
+
+        // records longer than a line reset the sub-offset at every record
+        let buf: Vec<u8> = vec![0; 40];
+        cmd_options.record_size = Some(20);
+        cmd_options.cannonical = true;
+        cmd_options.two_bytes_hex = false;
+        let lines: Vec<String> = Formatter::new(buf, 0, &cmd_options).collect();
+        let offsets: Vec<&str> = lines.iter().map(|l| l.get(..13).unwrap_or(l)).collect();
+        assert_eq!(
+            vec!["0000000 +0000", "0000010 +0010", "", "0000014 +0000", "0000024 +0010", "0000028"],
+            offsets
+        );
+        assert!(lines[0].ends_with("  |................|"));
+        assert_eq!(lines[0].find('|'), lines[1].find('|'));
+
+        let inputs = vec!["hexdump", "--record-size", "0", "f1"];
+        assert!(read_arguments(&inputs).is_err());
+    }
+
+    #[test]
+    fn ts_formatter_final_offset() {
+        let buf: Vec<u8> = (0x41..0x53).collect();
+        let cmd_options = read_arguments(vec!["hexdump", "-C", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("0000012", lines[2]);
+
+        let cmd_options = read_arguments(vec!["hexdump", "-C", "--no-final-offset", "f1"]).unwrap();
+        assert!(cmd_options.no_final_offset);
+        let lines: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[1].starts_with("0000010  51 52"));
+
+        // a skip offset past the end of the input prints the final offset
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.offset = 100;
+        let lines: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        assert_eq!(vec!["0000012"], lines);
+        cmd_options.no_final_offset = true;
+        assert_eq!(0, Formatter::new(buf, 0, &cmd_options).count());
+    }
+
+    #[test]
+    fn ts_formatter_plain_and_only_offsets() {
+        let buf: Vec<u8> = (0x41..0x53).collect();
+        let cmd_options = read_arguments(vec!["hexdump", "--plain", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        assert_eq!(vec!["4241  4443  4645  4847  4a49  4c4b  4e4d  504f", "5251"], lines);
+
+        let cmd_options = read_arguments(vec!["hexdump", "-C", "--plain", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            "41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50   |ABCDEFGHIJKLMNOP|",
+            lines[0]
+        );
+        assert_eq!(lines[0].find('|'), lines[1].find('|'));
+        assert!(lines[1].starts_with("51 52 "));
+
+        let cmd_options = read_arguments(vec!["hexdump", "--only-offsets", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(buf, 0, &cmd_options).collect();
+        assert_eq!(vec!["0000000", "0000010", "0000012"], lines);
+    }
+
+    #[test]
+    fn ts_formatter_layout() {
+        let buf: Vec<u8> = b"Hello, xxd!\n\x00\x01\xffABCDEFGHI".to_vec();
+        let cmd_options = read_arguments(vec!["hexdump", "--layout=xxd", "f1"]).unwrap();
+        assert_eq!(Some(Layout::Xxd), cmd_options.layout);
+        let lines: Vec<String> = Formatter::new(buf.clone(), 0, &cmd_options).collect();
+        assert_eq!(
+            vec![
+                "00000000: 4865 6c6c 6f2c 2078 7864 210a 0001 ff41  Hello, xxd!....A",
+                "00000010: 4243 4445 4647 4849                      BCDEFGHI",
+            ],
+            lines
+        );
+
+        let cmd_options = read_arguments(vec!["hexdump", "--layout", "od", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(buf[..20].to_vec(), 0, &cmd_options).collect();
+        assert_eq!(
+            vec![
+                "0000000 062510 066154 026157 074040 062170 005041 000400 040777",
+                "0000020 041502 042504",
+                "0000024",
+            ],
+            lines
+        );
+
+        let cmd_options = read_arguments(vec!["hexdump", "--layout=hexdump", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(buf[16..].to_vec(), 0, &cmd_options).collect();
+        assert_eq!(vec!["0000000 4342 4544 4746 4948", "0000008"], lines);
+    }
+
+    #[test]
+    fn ts_seek_and_discard_offset() {
+        let input: Vec<u8> = (0..100u8).collect();
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.cannonical = true;
+        cmd_options.two_bytes_hex = false;
+
+        for offset in [0u64, 20, 99, 100, 150].iter() {
+            // seekable input
+            let mut seekable = io::Cursor::new(input.clone());
+            let seek_start = seek_to_offset(&mut seekable, *offset).unwrap();
+            let mut seek_buf = Vec::new();
+            seekable.read_to_end(&mut seek_buf).unwrap();
+
+            // non seekable input
+            let mut pipe: &[u8] = &input;
+            let discard_start = discard_to_offset(&mut pipe, *offset).unwrap();
+            let discard_buf = pipe.to_vec();
+
+            assert_eq!(seek_start, discard_start);
+            assert_eq!(seek_buf, discard_buf);
+
+            // the dump is the same as the one of the whole input
+            cmd_options.offset = *offset as i32;
+            let whole: Vec<String> = Formatter::new(input.clone(), 0, &cmd_options).collect();
+            let seek: Vec<String> =
+                Formatter::new(seek_buf, seek_start as usize, &cmd_options).collect();
+            let discard: Vec<String> =
+                Formatter::new(discard_buf, discard_start as usize, &cmd_options).collect();
+            assert_eq!(seek, discard);
+            if *offset <= 100 {
+                assert_eq!(whole, seek);
+            }
+        }
+
+        // the offset is reached on a file
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("f1");
+        fs::write(&path, &input).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        assert_eq!((90, input[90..].to_vec()), get_input(&path, 90).unwrap());
+        assert_eq!((100, Vec::new()), get_input(&path, 1000).unwrap());
+    }
+
+    #[test]
+    fn ts_find_signature() {
+        assert_eq!(Some(vec![0x50, 0x4b]), parse_signature("504b"));
+        assert_eq!(Some(vec![0x50, 0x4b]), parse_signature("0x504B"));
+        assert_eq!(None, parse_signature(""));
+        assert_eq!(None, parse_signature("504"));
+        assert_eq!(None, parse_signature("50zz"));
+
+        let inputs = vec!["hexdump", "--find", "504b", "-n", "4", "f1"];
+        let cmd_options = read_arguments(&inputs).unwrap();
+        assert_eq!(Some(vec![0x50, 0x4b]), cmd_options.find);
+        let inputs = vec!["hexdump", "--find", "5", "f1"];
+        assert!(read_arguments(&inputs).is_err());
+
+        let mut buf: Vec<u8> = (0..64u8).collect();
+        buf[37] = 0x50;
+        buf[38] = 0x4b;
+        assert_eq!(Some(37), find_signature(&buf, &[0x50, 0x4b]));
+        assert_eq!(Some(0), find_signature(&buf, &[0x00]));
+        assert_eq!(None, find_signature(&buf, &[0x4b, 0x50]));
+
+        // the dump starts at the offset of the signature
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.cannonical = true;
+        cmd_options.two_bytes_hex = false;
+        cmd_options.offset = 37;
+        let lines: Vec<String> = Formatter::new(buf[..41].to_vec(), 0, &cmd_options).collect();
+        assert_eq!(
+            vec![
+                "0000025  50 4b 27 28                                        |PK'(|",
+                "0000029",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn ts_diff_bytes_shift() {
+        let a: Vec<u8> = (0..32u8).collect();
+        assert!(diff_bytes(&a, &a, 0).is_empty());
+
+        // b is a with a prefix of 3 bytes
+        let mut b: Vec<u8> = vec![0xaa, 0xbb, 0xcc];
+        b.extend_from_slice(&a);
+        assert_eq!(35, diff_bytes(&a, &b, 0).len());
+        assert!(diff_bytes(&a, &b, 3).is_empty());
+
+        let inputs = vec!["hexdump", "--diff", "f2", "--shift", "3", "f1"];
+        let cmd_options = read_arguments(&inputs).unwrap();
+        assert_eq!(Some(String::from("f2")), cmd_options.diff_file);
+        assert_eq!(3, cmd_options.shift);
+
+        // a changed byte and a missing byte
+        b[3 + 5] = 0xff;
+        b.pop();
+        let differences = diff_bytes(&a, &b, 3);
+        assert_eq!(vec![(5, Some(5), Some(0xff)), (31, Some(31), None)], differences);
+        assert_eq!("0000005 05 ff", format_difference(&differences[0]));
+        assert_eq!("000001f 1f --", format_difference(&differences[1]));
+
+        // a shift longer than b
+        assert_eq!(2, diff_bytes(&[1, 2], &[1, 2], 5).len());
+    }
+
+    #[test]
+    fn ts_should_page() {
+        // output longer than the terminal
+        assert!(should_page(true, Some(24), 25));
+
+        // output fits in the terminal
+        assert!(!should_page(true, Some(24), 24));
+        assert!(!should_page(true, Some(24), 3));
+
+        // not a terminal, or unknown height, never page
+        assert!(!should_page(false, Some(24), 100));
+        assert!(!should_page(true, None, 100));
+    }
+
+    #[test]
+    fn ts_find_ascii_runs() {
+        let mut buf: Vec<u8> = vec![0x00, 0x01];
+        buf.extend_from_slice(b"hello");
+        buf.extend_from_slice(&[0x0a, 0xff]);
+        buf.extend_from_slice(b"abc");
+        buf.push(0x00);
+        buf.extend_from_slice(b"world!");
+
+        let runs = find_ascii_runs(&buf, 0, 4);
+        assert_eq!(2, runs.len());
+        assert_eq!((2, String::from("hello")), runs[0]);
+        assert_eq!((13, String::from("world!")), runs[1]);
+
+        // offsets are relative to the start of the input
+        let runs = find_ascii_runs(&buf[2..], 2, 3);
+        assert_eq!(3, runs.len());
+        assert_eq!((2, String::from("hello")), runs[0]);
+        assert_eq!((9, String::from("abc")), runs[1]);
+        assert_eq!((13, String::from("world!")), runs[2]);
+
+        // no run long enough
+        assert_eq!(0, find_ascii_runs(b"ab\x00cd", 0, 4).len());
     }
 
     #[test]
@@ -489,7 +1601,7 @@ mod hexdump_ts {
         let mut cmd_options = CommandLineOptions::new();
         cmd_options.one_byte_octal = true;
         cmd_options.two_bytes_hex = false;
-        let fmt: Formatter = Formatter::new(v, &cmd_options);
+        let fmt: Formatter = Formatter::new(v, 0, &cmd_options);
         assert_eq!(true, fmt.oct_output);
         assert_eq!(false, fmt.char_output);
         assert_eq!(false, fmt.cannonical);
@@ -502,7 +1614,7 @@ mod hexdump_ts {
         let mut cmd_options = CommandLineOptions::new();
         cmd_options.one_byte_octal = true;
         cmd_options.two_bytes_hex = false;
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
 
         let mut expected_lines: Vec<String> = Vec::new();
         expected_lines.push(String::from(
@@ -523,7 +1635,7 @@ mod hexdump_ts {
         ));
         expected_lines.push(String::from("0000010"));
 
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
         for (i, line) in fmt.enumerate() {
             assert_eq!(expected_lines[i], line, "line is: {}", line);
         }
@@ -534,19 +1646,87 @@ mod hexdump_ts {
         expected_lines.push(String::from("0000010  021"));
         expected_lines.push(String::from("0000011"));
 
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
         for (i, line) in fmt.enumerate() {
             assert_eq!(expected_lines[i], line, "line is: {}", line);
         }
     }
 
+    #[test]
+    fn ts_formatter_two_bytes_hex() {
+        let buf: Vec<u8> = vec![66, 67, 68, 69, 70, 71, 72, 73, 74, 75];
+        let cmd_options = CommandLineOptions::new();
+        let fmt = Formatter::new(buf, 0, &cmd_options);
+        assert!(fmt.two_byte_output);
+        assert!(fmt.hex_output);
+
+        let expected_lines = vec!["0000000   4342  4544  4746  4948  4b4a", "000000a"];
+
+        let lines: Vec<String> = fmt.collect();
+        assert_eq!(expected_lines, lines);
+
+        // an odd number of bytes, the last word is padded with 0
+        let buf: Vec<u8> = (0x41..0x5a).collect();
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.two_bytes_hex = true;
+        let fmt = Formatter::new(buf, 0, &cmd_options);
+
+        let expected_lines = vec![
+            "0000000   4241  4443  4645  4847  4a49  4c4b  4e4d  504f",
+            "0000010   5251  5453  5655  5857  0059",
+            "0000019",
+        ];
+
+        let lines: Vec<String> = fmt.collect();
+        assert_eq!(expected_lines, lines);
+    }
+
+    #[test]
+    fn ts_formatter_two_bytes_octal() {
+        let buf: Vec<u8> = vec![0x41, 0x42, 0xff, 0xff, 0x07];
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.two_bytes_hex = false;
+        cmd_options.two_bytes_octal = true;
+        let fmt = Formatter::new(buf, 0, &cmd_options);
+
+        let expected_lines = vec!["0000000   041101  177777  000007", "0000005"];
+
+        let lines: Vec<String> = fmt.collect();
+        assert_eq!(expected_lines, lines);
+    }
+
+    #[test]
+    fn ts_formatter_two_bytes_decimal() {
+        let cmd_options = read_arguments(vec!["hexdump", "-d", "f1"]).unwrap();
+        assert!(cmd_options.two_bytes_decimal);
+        assert!(!cmd_options.two_bytes_hex);
+        let fmt = Formatter::new(vec![0x01, 0x00, 0xFF, 0xFF], 0, &cmd_options);
+        assert!(fmt.dec_output);
+        assert!(!fmt.hex_output);
+
+        let lines: Vec<String> = fmt.collect();
+        assert_eq!(vec!["0000000   00001  65535", "0000004"], lines);
+
+        let buf: Vec<u8> = vec![0, 1, 2, 0, 4, 0];
+        let lines: Vec<String> = Formatter::new(buf, 0, &cmd_options).collect();
+        assert_eq!(vec!["0000000   00256  00002  00004", "0000006"], lines);
+
+        // a last odd byte is the low byte of the word
+        let cmd_options = read_arguments(vec!["hexdump", "--two-bytes-decimal", "f1"]).unwrap();
+        let lines: Vec<String> = Formatter::new(vec![0x00, 0x01, 0x02], 0, &cmd_options).collect();
+        assert_eq!(vec!["0000000   00256  00002", "0000003"], lines);
+        let buf: Vec<u8> = vec![0, 1, 2, 0, 4, 0, 2];
+        let lines: Vec<String> = Formatter::new(buf, 0, &cmd_options).collect();
+        assert_eq!(vec!["0000000   00256  00002  00004  00002", "0000007"], lines);
+    }
+
     #[test]
     fn ts_formatter_one_char() {
         let buf: Vec<u8> = vec![66, 67, 68, 69, 70, 71, 72, 73, 74, 75];
         let mut cmd_options = CommandLineOptions::new();
         cmd_options.one_byte_char = true;
         cmd_options.two_bytes_hex = false;
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
 
         let mut expected_lines: Vec<String> = Vec::new();
         expected_lines.push(String::from(
@@ -565,7 +1745,7 @@ mod hexdump_ts {
         let mut cmd_options = CommandLineOptions::new();
         cmd_options.cannonical = true;
         cmd_options.two_bytes_hex = false;
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
         let mut expected_lines: Vec<String> = Vec::new();
 
 
@@ -581,7 +1761,7 @@ mod hexdump_ts {
         }
 
         let buf: Vec<u8> = vec![66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81];
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
 
         // test one complete line
         let _ = expected_lines.pop();
@@ -598,7 +1778,7 @@ mod hexdump_ts {
 
         // test 2 lines - second incomplete and ends in \n
         let buf: Vec<u8> = vec![66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 0x0a];
-        let fmt = Formatter::new(buf, &cmd_options);
+        let fmt = Formatter::new(buf, 0, &cmd_options);
 
         expected_lines.pop();
         expected_lines.push(String::from(
@@ -613,46 +1793,4 @@ mod hexdump_ts {
         }
     }
 
-    #[test]
-    fn formatter_two_byte_decimal() {
-        let buf: Vec<u8> = vec![0, 1, 2, 0, 4, 0];
-        let mut cmd_options = CommandLineOptions::new();
-        cmd_options.two_bytes_decimal = true;
-        cmd_options.two_bytes_hex = false;
-        let fmt = Formatter::new(buf, &cmd_options);
-        let mut expected_lines: Vec<String> = Vec::new();
-
-
-        // test one incomplete line - even bytes
-        expected_lines.push(String::from(
-            format!("0000000   00256  00002  00004")
-        ));
-        expected_lines.push(String::from(
-            format!("0000006")
-        ));
-        for (i, line) in fmt.enumerate() {
-            assert_eq!(expected_lines[i], line, "line is: {}", line);
-        }
-
-
-
-        // test one incomplete line - odd bytes
-
-        let buf: Vec<u8> = vec![0, 1, 2, 0, 4, 0, 2];
-        let fmt = Formatter::new(buf, &cmd_options);
-
-        let _ = expected_lines.pop();
-        let _ = expected_lines.pop();
-        expected_lines.push(String::from(
-            format!("0000000   00256  00002  00004  00002")
-        ));
-        expected_lines.push(String::from(
-            format!("0000007")
-        ));
-        for (i, line) in fmt.enumerate() {
-            assert_eq!(expected_lines[i], line, "line is: {}", line);
-        }
-
-    }
-
 } // mod hexdump_ts
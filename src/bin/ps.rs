@@ -1,15 +1,1293 @@
-use procfs;
+/// A ps clone written in Rust
+///
+/*
+ * https://pubs.opengroup.org/onlinepubs/9699919799/utilities/ps.html
+ * https://docs.rs/procfs/0.9.1/procfs/
+ *
+ */
+use clap::{App, Arg};
+use procfs::process::Process;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+extern crate toolslib;
+use crate::toolslib::ErrCode;
+
+const VERSION: &str = "ver. 0.0.1";
+
+/// The columns of the user oriented format selected with the BSD `u` option
+const USER_FORMAT: &str = "user,pid,%cpu,vsz,rss,tty,time,cmd";
+
+/// The options of `read_arguments` that take their value in the next argument
+const OPTIONS_WITH_VALUE: [&str; 4] = ["-o", "--watch", "--show-env", "--details"];
+
+/// The columns displayed by `--details`, every field known for a process
+const DETAIL_COLUMNS: [Column; 13] = [
+    Column::Pid,
+    Column::Ppid,
+    Column::User,
+    Column::Tty,
+    Column::Stat,
+    Column::Policy,
+    Column::Time,
+    Column::Cpu,
+    Column::Sz,
+    Column::Rss,
+    Column::Vsz,
+    Column::Fds,
+    Column::Cmd,
+];
+
+/// The columns that can be displayed in the output table.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Column {
+    User,
+    Pid,
+    Ppid,
+    Tty,
+    Stat,
+    Time,
+    Cpu,
+    Sz,
+    Rss,
+    Vsz,
+    Fds,
+    Policy,
+    /// The environment variable selected with `--show-env`
+    Env,
+    Cmd,
+}
+
+impl Column {
+    /// Returns the column for a name given with `-o`.
+    fn from_name(name: &str) -> Option<Column> {
+        match name {
+            "user" => Some(Column::User),
+            "pid" => Some(Column::Pid),
+            "ppid" => Some(Column::Ppid),
+            "tty" => Some(Column::Tty),
+            "stat" => Some(Column::Stat),
+            "time" => Some(Column::Time),
+            "%cpu" | "pcpu" => Some(Column::Cpu),
+            "sz" => Some(Column::Sz),
+            "rss" => Some(Column::Rss),
+            "vsz" => Some(Column::Vsz),
+            "fds" => Some(Column::Fds),
+            "policy" => Some(Column::Policy),
+            "cmd" | "comm" => Some(Column::Cmd),
+            _ => None,
+        }
+    }
+
+    /// Returns the header of the column.
+    fn header(&self) -> &'static str {
+        match self {
+            Column::User => "USER",
+            Column::Pid => "PID",
+            Column::Ppid => "PPID",
+            Column::Tty => "TTY",
+            Column::Stat => "STAT",
+            Column::Time => "TIME",
+            Column::Cpu => "%CPU",
+            Column::Sz => "SZ",
+            Column::Rss => "RSS",
+            Column::Vsz => "VSZ",
+            Column::Fds => "FDS",
+            Column::Policy => "POLICY",
+            Column::Env => "ENV",
+            Column::Cmd => "CMD",
+        }
+    }
+}
+
+/// A structure that stores the parsed flags from command line.
+struct CommandLineOptions {
+    all_processes: bool,
+    no_kthreads: bool,
+    human_readable: bool,
+    columns: Vec<Column>,
+    /// Refresh interval in seconds of the `--watch` mode
+    watch: Option<u64>,
+    summary: bool,
+    /// Name of the environment variable displayed with `--show-env`
+    show_env: Option<String>,
+    /// The process displayed vertically with `--details`
+    details: Option<i32>,
+}
+
+impl CommandLineOptions {
+    /// Initializes the CommandLineOptions to default values
+    fn new() -> CommandLineOptions {
+        CommandLineOptions {
+            all_processes: false,
+            no_kthreads: false,
+            human_readable: false,
+            columns: vec![Column::Pid, Column::Tty, Column::Time, Column::Cmd],
+            watch: None,
+            summary: false,
+            show_env: None,
+            details: None,
+        }
+    }
+
+    /// Returns true if the open file descriptors are displayed, in the
+    /// `FDS` column or with `--details`.
+    fn shows_fds(&self) -> bool {
+        self.details.is_some() || self.columns.contains(&Column::Fds)
+    }
+}
+
+/// The information of a process displayed in the output table.
+struct ProcessInfo {
+    /// Name of the owner, or the uid if it has no name
+    user: String,
+    pid: i32,
+    ppid: i32,
+    tty: String,
+    /// State of the process, like `R` or `S`
+    state: char,
+    /// Process group id
+    pgrp: i32,
+    /// Foreground process group of the controlling terminal, -1 without one
+    tpgid: i32,
+    time: f32,
+    /// CPU time in clock ticks, user and system
+    ticks: u64,
+    /// Percentage of CPU used, averaged over the lifetime of the process
+    /// or over the last refresh interval in `--watch` mode
+    cpu: f32,
+    /// Size of the core image in pages
+    sz: u64,
+    /// Resident set size in KB
+    rss: u64,
+    /// Virtual memory size in KB
+    vsz: u64,
+    /// Size of a memory page in KB
+    page_size: u64,
+    /// Number of open file descriptors, if readable
+    fds: Option<usize>,
+    /// Scheduling policy, None for kernels that do not report it
+    policy: Option<u32>,
+    /// Value of the `--show-env` variable, None if it is not set or the
+    /// environment can not be read
+    env: Option<String>,
+    cmd: String,
+    cmdline: Vec<String>,
+}
+
+impl ProcessInfo {
+    /// Extracts the displayed fields from a `procfs` process.
+    ///
+    /// # Arguments
+    /// * `prc` - The process read from `/proc`
+    /// * `tps` - The number of clock ticks per second
+    /// * `page_size` - The size of a memory page in bytes
+    /// * `uptime` - The seconds elapsed since the system booted
+    /// * `env_var` - The environment variable to read, if any
+    /// * `count_fds` - Whether to count the open file descriptors
+    fn from_process(
+        prc: &Process,
+        tps: i64,
+        page_size: i64,
+        uptime: f64,
+        env_var: Option<&str>,
+        count_fds: bool,
+    ) -> ProcessInfo {
+        let page_size = (page_size / 1024) as u64;
+        let ticks = prc.stat.utime + prc.stat.stime;
+        let elapsed = uptime - prc.stat.starttime as f64 / tps as f64;
+        ProcessInfo {
+            user: match users::get_user_by_uid(prc.owner) {
+                Some(user) => user.name().to_string_lossy().to_string(),
+                None => prc.owner.to_string(),
+            },
+            pid: prc.stat.pid,
+            ppid: prc.stat.ppid,
+            tty: format!("pty/{}", prc.stat.tty_nr().1),
+            state: prc.stat.state,
+            pgrp: prc.stat.pgrp,
+            tpgid: prc.stat.tpgid,
+            time: ticks as f32 / (tps as f32),
+            ticks,
+            cpu: cpu_percent(ticks, tps, elapsed),
+            sz: prc.stat.vsize / (page_size * 1024),
+            rss: prc.stat.rss.max(0) as u64 * page_size,
+            vsz: prc.stat.vsize / 1024,
+            page_size,
+            fds: if count_fds {
+                count_dir_entries(&Path::new("/proc").join(prc.pid.to_string()).join("fd"))
+            } else {
+                None
+            },
+            policy: prc.stat.policy,
+            env: env_var.and_then(|name| {
+                let path = Path::new("/proc").join(prc.pid.to_string()).join("environ");
+                fs::read(path)
+                    .ok()
+                    .and_then(|buf| parse_environ(&buf).remove(name))
+            }),
+            cmd: prc.stat.comm.clone(),
+            cmdline: prc.cmdline().unwrap_or_default(),
+        }
+    }
+}
+
+/// The totals of a list of processes displayed with `--summary`.
+#[derive(Debug, PartialEq)]
+struct Summary {
+    processes: usize,
+    /// Resident set size in KB
+    rss: u64,
+    /// CPU time in seconds
+    time: f32,
+}
+
+/// Read the command line arguments and parse them into the CommandLineOptions
+/// structure.
+fn read_arguments<I, T>(itr: I) -> CommandLineOptions
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let mut cmd_options = CommandLineOptions::new();
+    let matches = App::new("ps: ps clone command written in Rust")
+        .version(VERSION)
+        .author("Manuel Berrocal")
+        .about("Report process status")
+        .arg(
+            Arg::with_name("every")
+                .short("e")
+                .takes_value(false)
+                .help("select all processes, including ps itself"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .short("A")
+                .takes_value(false)
+                .help("select all processes, identical to -e"),
+        )
+        .arg(
+            Arg::with_name("human_readable")
+                .short("h")
+                .long("human")
+                .takes_value(false)
+                .help("print memory sizes in human readable format (e.g. 512M, 1.2G)"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("o")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .validator(|v| match parse_columns(&v) {
+                    Some(_) => Ok(()),
+                    None => Err(String::from(
+                        "valid columns are: user, pid, ppid, tty, stat, time, %cpu, sz, rss, vsz, fds, policy, cmd",
+                    )),
+                })
+                .help("comma separated list of columns to display"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|v| match v.parse::<u64>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("the interval must be a positive integer")),
+                })
+                .help("refresh the table every SECONDS, %CPU is measured between refreshes"),
+        )
+        .arg(
+            Arg::with_name("summary")
+                .long("summary")
+                .takes_value(false)
+                .help("print the total of processes, RSS and CPU time after the table"),
+        )
+        .arg(
+            Arg::with_name("no_kthreads")
+                .long("no-kthreads")
+                .takes_value(false)
+                .help("do not list kernel threads"),
+        )
+        .arg(
+            Arg::with_name("show_env")
+                .long("show-env")
+                .takes_value(true)
+                .value_name("VAR")
+                .help("display the value of the environment variable VAR of each process"),
+        )
+        .arg(
+            Arg::with_name("details")
+                .long("details")
+                .takes_value(true)
+                .value_name("PID")
+                .conflicts_with_all(&["watch", "summary"])
+                .validator(|v| match v.parse::<i32>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("PID must be a positive integer")),
+                })
+                .help("print every field of the process PID, one per line"),
+        )
+        .get_matches_from(itr);
+
+    if matches.is_present("every") || matches.is_present("all") {
+        cmd_options.all_processes = true;
+    }
+
+    if matches.is_present("no_kthreads") {
+        cmd_options.no_kthreads = true;
+    }
+
+    if matches.is_present("human_readable") {
+        cmd_options.human_readable = true;
+    }
+
+    if matches.is_present("summary") {
+        cmd_options.summary = true;
+    }
+
+    // The validator guarantees that the columns are valid
+    if let Some(columns) = matches.value_of("format").and_then(parse_columns) {
+        cmd_options.columns = columns;
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(seconds) = matches.value_of("watch") {
+        cmd_options.watch = Some(seconds.parse().unwrap());
+    }
+
+    // The validator guarantees that the value parses
+    if let Some(pid) = matches.value_of("details") {
+        cmd_options.details = Some(pid.parse().unwrap());
+    }
+
+    // The variable is displayed before the command, which takes the rest of
+    // the line
+    if let Some(name) = matches.value_of("show_env") {
+        cmd_options.show_env = Some(String::from(name));
+        let position = match cmd_options.columns.last() {
+            Some(Column::Cmd) => cmd_options.columns.len() - 1,
+            _ => cmd_options.columns.len(),
+        };
+        cmd_options.columns.insert(position, Column::Env);
+    }
+
+    cmd_options
+}
+
+/// Translates the BSD style options bundled without a dash, like `ps aux`,
+/// into their GNU style equivalents.
+///
+/// The program name, the arguments starting with `-` and the values of the
+/// options in `OPTIONS_WITH_VALUE`, like `--show-env ax`, are not modified.
+/// The BSD options are:
+/// * `a` - all the processes, like `-e`
+/// * `u` - the user oriented format, like `-o user,pid,%cpu,vsz,rss,tty,time,cmd`
+/// * `x` - include the processes without a terminal; ps does not filter the
+///   processes by terminal, it is accepted for compatibility
+fn expand_bsd_options<I, T>(itr: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let is_bsd_bundle = |arg: &str| !arg.is_empty() && arg.chars().all(|c| "aux".contains(c));
+
+    let mut args: Vec<OsString> = Vec::new();
+    let (mut all, mut user_format) = (false, false);
+    let mut is_value = false;
+    for (i, arg) in itr.into_iter().map(Into::into).enumerate() {
+        match arg.to_str() {
+            Some(bundle) if i > 0 && !is_value && is_bsd_bundle(bundle) => {
+                all |= bundle.contains('a');
+                user_format |= bundle.contains('u');
+            }
+            _ => {
+                is_value = !is_value
+                    && arg
+                        .to_str()
+                        .is_some_and(|option| OPTIONS_WITH_VALUE.contains(&option));
+                args.push(arg);
+            }
+        }
+    }
+
+    if all {
+        args.push(OsString::from("-e"));
+    }
+    if user_format {
+        args.push(OsString::from("-o"));
+        args.push(OsString::from(USER_FORMAT));
+    }
+    args
+}
+
+/// Parses a comma separated list of column names, returns None if any of the
+/// names is not a valid column.
+fn parse_columns(format: &str) -> Option<Vec<Column>> {
+    format
+        .split(',')
+        .map(|name| Column::from_name(name.trim()))
+        .collect()
+}
+
+/// Parses the NUL separated `NAME=value` entries of `/proc/<pid>/environ`
+/// into a map, the entries without `=` are ignored.
+fn parse_environ(buf: &[u8]) -> HashMap<String, String> {
+    buf.split(|&b| b == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            entry
+                .find('=')
+                .map(|i| (entry[..i].to_string(), entry[i + 1..].to_string()))
+        })
+        .collect()
+}
+
+/// Returns the number of entries in a directory, or None if it can not be read.
+///
+/// Used to count the open file descriptors in `/proc/<pid>/fd`, which is not
+/// readable for the processes of other users.
+fn count_dir_entries(path: &Path) -> Option<usize> {
+    fs::read_dir(path).ok().map(|entries| entries.count())
+}
+
+/// Returns the percentage of CPU used by `ticks` clock ticks in `seconds`.
+///
+/// The seconds since boot do not fit in the precision of an `f32`, only the
+/// resulting percentage is narrowed.
+fn cpu_percent(ticks: u64, tps: i64, seconds: f64) -> f32 {
+    if seconds <= 0.0 {
+        return 0.0;
+    }
+    (ticks as f64 / tps as f64 / seconds * 100.0) as f32
+}
+
+/// Returns the percentage of CPU used by a process between two samples.
+///
+/// # Arguments
+/// * `previous` - The CPU ticks of the process in the previous sample
+/// * `current` - The CPU ticks of the process in the current sample
+/// * `tps` - The number of clock ticks per second
+/// * `interval` - The seconds elapsed between both samples
+fn cpu_percent_delta(previous: u64, current: u64, tps: i64, interval: f32) -> f32 {
+    cpu_percent(current.saturating_sub(previous), tps, f64::from(interval))
+}
+
+/// Replaces the lifetime %CPU of the processes with the one used since the
+/// previous sample. Processes that were not in the previous sample keep their
+/// lifetime average, they started during the interval.
+fn apply_cpu_deltas(
+    processes: &mut [ProcessInfo],
+    previous: &HashMap<i32, u64>,
+    tps: i64,
+    interval: f32,
+) {
+    for info in processes.iter_mut() {
+        if let Some(ticks) = previous.get(&info.pid) {
+            info.cpu = cpu_percent_delta(*ticks, info.ticks, tps, interval);
+        }
+    }
+}
+
+/// Returns true if the process group is the foreground process group of its
+/// controlling terminal. `tpgid` is -1 for processes without a terminal.
+fn is_foreground(pgrp: i32, tpgid: i32) -> bool {
+    tpgid > 0 && pgrp == tpgid
+}
+
+/// Returns the STAT column of a process, the state followed by `+` when the
+/// process is in the foreground process group.
+fn format_stat(info: &ProcessInfo) -> String {
+    if is_foreground(info.pgrp, info.tpgid) {
+        format!("{}+", info.state)
+    } else {
+        info.state.to_string()
+    }
+}
+
+/// Returns the name of a scheduling policy, the values are the `SCHED_*`
+/// constants of `sched.h`. Unknown policies are returned as a number.
+fn policy_name(policy: Option<u32>) -> String {
+    let name = match policy {
+        Some(0) => "SCHED_OTHER",
+        Some(1) => "SCHED_FIFO",
+        Some(2) => "SCHED_RR",
+        Some(3) => "SCHED_BATCH",
+        Some(5) => "SCHED_IDLE",
+        Some(6) => "SCHED_DEADLINE",
+        Some(policy) => return policy.to_string(),
+        None => "-",
+    };
+    String::from(name)
+}
+
+/// Returns a memory size in KB formatted with a binary suffix, like `512M` or `1.2G`.
+fn humanize_kb(kb: u64) -> String {
+    const SUFFIXES: [&str; 4] = ["M", "G", "T", "P"];
+
+    if kb < 1024 {
+        return format!("{}K", kb);
+    }
+    let mut size = kb as f64 / 1024.0;
+    let mut suffix = 0;
+    // 1023.95 and above would be rounded up to 1024.0
+    while size >= 1023.95 && suffix < SUFFIXES.len() - 1 {
+        size /= 1024.0;
+        suffix += 1;
+    }
+    format!("{:.1}{}", size, SUFFIXES[suffix])
+}
+
+/// Returns the value of a column for a process.
+fn column_value(column: Column, info: &ProcessInfo, human_readable: bool) -> String {
+    let memory = |kb: u64, raw: u64| {
+        if human_readable {
+            humanize_kb(kb)
+        } else {
+            raw.to_string()
+        }
+    };
+    match column {
+        Column::User => info.user.clone(),
+        Column::Pid => info.pid.to_string(),
+        Column::Ppid => info.ppid.to_string(),
+        Column::Tty => info.tty.clone(),
+        Column::Stat => format_stat(info),
+        Column::Time => info.time.to_string(),
+        Column::Cpu => format!("{:.1}", info.cpu),
+        Column::Sz => memory(info.sz * info.page_size, info.sz),
+        Column::Rss => memory(info.rss, info.rss),
+        Column::Vsz => memory(info.vsz, info.vsz),
+        Column::Fds => match info.fds {
+            Some(fds) => fds.to_string(),
+            None => String::from("-"),
+        },
+        Column::Policy => policy_name(info.policy),
+        Column::Env => match &info.env {
+            Some(value) => value.clone(),
+            None => String::from("-"),
+        },
+        Column::Cmd => info.cmd.clone(),
+    }
+}
+
+/// Returns the header of a column, the name of the variable for `--show-env`.
+fn column_header(column: Column, cmd_options: &CommandLineOptions) -> String {
+    match (column, &cmd_options.show_env) {
+        (Column::Env, Some(name)) => name.clone(),
+        _ => column.header().to_string(),
+    }
+}
+
+/// Returns the `--details` lines of a process, a `HEADER: value` line for
+/// every field with the values aligned after the longest header.
+///
+/// The variable of `--show-env` is displayed before the command, the whole
+/// command line is displayed after it.
+fn format_details(info: &ProcessInfo, cmd_options: &CommandLineOptions) -> Vec<String> {
+    let mut columns = DETAIL_COLUMNS.to_vec();
+    if cmd_options.show_env.is_some() {
+        columns.insert(columns.len() - 1, Column::Env);
+    }
+    let mut fields: Vec<(String, String)> = columns
+        .iter()
+        .map(|c| {
+            (
+                column_header(*c, cmd_options),
+                column_value(*c, info, cmd_options.human_readable),
+            )
+        })
+        .collect();
+    fields.push((String::from("ARGS"), info.cmdline.join(" ")));
+
+    let width = fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0) + 1;
+    fields
+        .iter()
+        .map(|(key, value)| format!("{:<width$} {}", format!("{}:", key), value, width = width))
+        .map(|line| line.trim_end().to_string())
+        .collect()
+}
+
+/// Minimum width of the PID and PPID columns
+const PID_WIDTH: usize = 5;
+
+/// Returns the width of the PID and PPID columns, wide enough for the longest
+/// pid of the processes.
+///
+/// The pids grow up to `/proc/sys/kernel/pid_max`, which can be 4194304, so
+/// a fixed width misaligns the table on some systems.
+fn pid_width(processes: &[ProcessInfo]) -> usize {
+    processes
+        .iter()
+        .flat_map(|info| [info.pid, info.ppid])
+        .map(|pid| pid.to_string().len())
+        .fold(PID_WIDTH, usize::max)
+}
+
+/// Returns a row of the output table with the values aligned to their column,
+/// the PID and PPID columns are `pid_width` wide.
+fn format_row(columns: &[Column], values: &[String], pid_width: usize) -> String {
+    let cells: Vec<String> = columns
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(i, (column, value))| match column {
+            Column::Cmd if i == columns.len() - 1 => value.clone(),
+            Column::User | Column::Tty | Column::Env | Column::Cmd => format!("{: <8}", value),
+            Column::Stat => format!("{: <4}", value),
+            Column::Policy => format!("{: <14}", value),
+            Column::Pid | Column::Ppid => format!("{: >width$}", value, width = pid_width),
+            _ => format!("{: >8}", value),
+        })
+        .collect();
+    cells.join(" ")
+}
+
+/// Returns the totals of the processes.
+fn summarize(processes: &[ProcessInfo]) -> Summary {
+    processes.iter().fold(
+        Summary {
+            processes: 0,
+            rss: 0,
+            time: 0.0,
+        },
+        |total, info| Summary {
+            processes: total.processes + 1,
+            rss: total.rss + info.rss,
+            time: total.time + info.time,
+        },
+    )
+}
+
+/// Returns the footer line printed with `--summary`.
+fn format_summary(summary: &Summary, human_readable: bool) -> String {
+    let rss = if human_readable {
+        humanize_kb(summary.rss)
+    } else {
+        summary.rss.to_string()
+    };
+    format!(
+        "TOTAL: {} processes, RSS {}, TIME {}",
+        summary.processes, rss, summary.time
+    )
+}
+
+/// Returns true if the process is a kernel thread.
+///
+/// Kernel threads are children of `kthreadd` (pid 2) or have an empty command line.
+fn is_kernel_thread(info: &ProcessInfo) -> bool {
+    info.ppid == 2 || info.cmdline.is_empty()
+}
+
+/// Returns true if the process is the running ps command.
+fn is_self(info: &ProcessInfo, self_pid: i32) -> bool {
+    info.pid == self_pid
+}
+
+/// Returns the processes that have to be displayed.
+///
+/// # Arguments
+/// * `processes` - A vector with all the processes in the system
+/// * `cmd_options` - The options read from the command line
+/// * `self_pid` - The pid of the running ps command
+///
+/// The ps process itself is only listed when `-e` or `-A` are given, kernel
+/// threads are excluded with `--no-kthreads`.
+fn select_processes(
+    processes: Vec<ProcessInfo>,
+    cmd_options: &CommandLineOptions,
+    self_pid: i32,
+) -> Vec<ProcessInfo> {
+    processes
+        .into_iter()
+        .filter(|info| cmd_options.all_processes || !is_self(info, self_pid))
+        .filter(|info| !(cmd_options.no_kthreads && is_kernel_thread(info)))
+        .collect()
+}
+
+/// Reads all the processes from `/proc`, with the value of the environment
+/// variable `env_var` if given and their open file descriptors if `count_fds`.
+///
+/// Returns the pid of the running ps command, the clock ticks per second and
+/// the processes.
+fn read_processes(
+    env_var: Option<&str>,
+    count_fds: bool,
+) -> Result<(i32, i64, Vec<ProcessInfo>), ErrCode> {
+    let (myself, tps, page_size, boot_time, all_processes) = match (
+        Process::myself(),
+        procfs::ticks_per_second(),
+        procfs::page_size(),
+        procfs::boot_time_secs(),
+        procfs::process::all_processes(),
+    ) {
+        (Ok(myself), Ok(tps), Ok(page_size), Ok(boot_time), Ok(all_processes)) => {
+            (myself, tps, page_size, boot_time, all_processes)
+        }
+        _ => {
+            eprintln!("Error reading the process information from /proc");
+            return Err(ErrCode::ErrorReadingProcFs);
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    let uptime = now - boot_time as f64;
+
+    let processes = all_processes
+        .iter()
+        .map(|prc| ProcessInfo::from_process(prc, tps, page_size, uptime, env_var, count_fds))
+        .collect();
+
+    Ok((myself.pid, tps, processes))
+}
+
+/// Prints the header and a row for every selected process.
+fn print_table(processes: Vec<ProcessInfo>, cmd_options: &CommandLineOptions, self_pid: i32) {
+    let columns = &cmd_options.columns;
+    let selected = select_processes(processes, cmd_options, self_pid);
+    let width = pid_width(&selected);
+
+    let headers: Vec<String> = columns
+        .iter()
+        .map(|c| column_header(*c, cmd_options))
+        .collect();
+    println!("{}", format_row(columns, &headers, width));
+
+    for info in &selected {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|c| column_value(*c, info, cmd_options.human_readable))
+            .collect();
+        println!("{}", format_row(columns, &values, width));
+    }
+
+    if cmd_options.summary {
+        println!(
+            "{}",
+            format_summary(&summarize(&selected), cmd_options.human_readable)
+        );
+    }
+}
+
+/// Prints the `--details` of the process `pid`.
+fn print_details(
+    processes: &[ProcessInfo],
+    cmd_options: &CommandLineOptions,
+    pid: i32,
+) -> Result<(), ErrCode> {
+    match processes.iter().find(|info| info.pid == pid) {
+        Some(info) => {
+            for line in format_details(info, cmd_options) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        None => {
+            eprintln!("ps: process {} not found", pid);
+            Err(ErrCode::ErrorProcessNotFound)
+        }
+    }
+}
 
 fn main() {
-    let ps = procfs::process::Process::myself().unwrap();
-    let tps = procfs::ticks_per_second().unwrap();
+    let cmd_options = read_arguments(expand_bsd_options(env::args_os()));
+
+    if let Some(pid) = cmd_options.details {
+        let result = read_processes(cmd_options.show_env.as_deref(), cmd_options.shows_fds())
+            .and_then(|(_, _, processes)| print_details(&processes, &cmd_options, pid));
+        if let Err(code) = result {
+            process::exit(code as i32);
+        }
+        return;
+    }
+
+    let interval = match cmd_options.watch {
+        Some(seconds) => seconds,
+        None => match read_processes(cmd_options.show_env.as_deref(), cmd_options.shows_fds()) {
+            Ok((self_pid, _, processes)) => {
+                print_table(processes, &cmd_options, self_pid);
+                return;
+            }
+            Err(code) => process::exit(code as i32),
+        },
+    };
 
-    println!("{: >5} {: <8} {: >8} {}", "PID", "TTY", "TIME", "CMD");
+    let mut previous: Option<HashMap<i32, u64>> = None;
+    loop {
+        let (self_pid, tps, mut processes) =
+            match read_processes(cmd_options.show_env.as_deref(), cmd_options.shows_fds()) {
+                Ok(sample) => sample,
+                Err(code) => process::exit(code as i32),
+            };
+        if let Some(previous) = &previous {
+            apply_cpu_deltas(&mut processes, previous, tps, interval as f32);
+        }
+        previous = Some(processes.iter().map(|p| (p.pid, p.ticks)).collect());
 
-    let tty = format!("pty/{}", ps.stat.tty_nr().1);
-    for prc in procfs::process::all_processes().unwrap() {
-        let total_time = (prc.stat.utime + prc.stat.stime) as f32 / (tps as f32);
-        println!("{: >5} {: <8} {: >8} {}", prc.stat.pid, tty, total_time, prc.stat.comm );
+        // clear the screen and move the cursor to the top left corner
+        print!("\x1b[2J\x1b[H");
+        print_table(processes, &cmd_options, self_pid);
+        thread::sleep(Duration::from_secs(interval));
     }
 }
+
+#[cfg(test)]
+mod ps_ts {
+    use super::*;
+
+    /// Builds a synthetic process for testing.
+    fn process_info(pid: i32, ppid: i32, cmdline: &[&str]) -> ProcessInfo {
+        ProcessInfo {
+            user: String::from("root"),
+            pid,
+            ppid,
+            tty: String::from("pty/0"),
+            state: 'S',
+            pgrp: pid,
+            tpgid: -1,
+            time: 0.0,
+            ticks: 0,
+            cpu: 0.0,
+            sz: 256,
+            rss: 1024,
+            vsz: 1536,
+            page_size: 4,
+            fds: None,
+            policy: Some(0),
+            env: None,
+            cmd: String::from("cmd"),
+            cmdline: cmdline.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// A synthetic process list: kthreadd, a kernel worker, init, a shell and ps.
+    fn process_list() -> Vec<ProcessInfo> {
+        vec![
+            process_info(2, 0, &[]),
+            process_info(10, 2, &[]),
+            process_info(1, 0, &["/sbin/init"]),
+            process_info(200, 1, &["bash"]),
+            process_info(300, 200, &["ps"]),
+        ]
+    }
+
+    #[test]
+    fn ts_read_arguments() {
+        let cmd_options = read_arguments(vec!["ps"]);
+        assert!(!cmd_options.all_processes);
+        assert!(!cmd_options.no_kthreads);
+
+        let cmd_options = read_arguments(vec!["ps", "-e"]);
+        assert!(cmd_options.all_processes);
+
+        let cmd_options = read_arguments(vec!["ps", "-A", "--no-kthreads"]);
+        assert!(cmd_options.all_processes);
+        assert!(cmd_options.no_kthreads);
+
+        assert_eq!(None, cmd_options.watch);
+
+        let cmd_options = read_arguments(vec!["ps", "--watch", "2", "-o", "pid,%cpu,cmd"]);
+        assert_eq!(Some(2), cmd_options.watch);
+        assert_eq!(
+            vec![Column::Pid, Column::Cpu, Column::Cmd],
+            cmd_options.columns
+        );
+
+        let cmd_options = read_arguments(vec!["ps", "-h", "-o", "pid,rss,vsz,cmd"]);
+        assert!(cmd_options.human_readable);
+        assert_eq!(
+            vec![Column::Pid, Column::Rss, Column::Vsz, Column::Cmd],
+            cmd_options.columns
+        );
+    }
+
+    #[test]
+    fn ts_expand_bsd_options() {
+        let cmd_options = read_arguments(expand_bsd_options(vec!["ps", "aux"]));
+        assert!(cmd_options.all_processes);
+        assert_eq!(parse_columns(USER_FORMAT).unwrap(), cmd_options.columns);
+        assert_eq!(Column::User, cmd_options.columns[0]);
+
+        // the order of the letters does not matter
+        assert_eq!(
+            expand_bsd_options(vec!["ps", "aux"]),
+            expand_bsd_options(vec!["ps", "xua"])
+        );
+
+        // only the options that are given
+        let cmd_options = read_arguments(expand_bsd_options(vec!["ps", "ax"]));
+        assert!(cmd_options.all_processes);
+        assert_eq!(CommandLineOptions::new().columns, cmd_options.columns);
+
+        // GNU style options are not modified
+        assert_eq!(
+            vec!["ps", "-e", "--no-kthreads"],
+            expand_bsd_options(vec!["ps", "-e", "--no-kthreads"])
+        );
+        let cmd_options = read_arguments(expand_bsd_options(vec!["ps", "--no-kthreads", "ax"]));
+        assert!(cmd_options.all_processes);
+        assert!(cmd_options.no_kthreads);
+
+        // the values of the options are not BSD options
+        assert_eq!(
+            vec!["ps", "--show-env", "ax"],
+            expand_bsd_options(vec!["ps", "--show-env", "ax"])
+        );
+        let cmd_options = read_arguments(expand_bsd_options(vec!["ps", "--show-env", "ax"]));
+        assert!(!cmd_options.all_processes);
+        assert_eq!(Some(String::from("ax")), cmd_options.show_env);
+        assert_eq!(
+            vec!["ps", "--show-env", "ax", "-e"],
+            expand_bsd_options(vec!["ps", "--show-env", "ax", "ax"])
+        );
+        assert_eq!(
+            vec!["ps", "--show-env=ax", "-e"],
+            expand_bsd_options(vec!["ps", "--show-env=ax", "ax"])
+        );
+    }
+
+    #[test]
+    fn ts_parse_columns() {
+        assert_eq!(
+            Some(vec![Column::Pid, Column::Sz, Column::Cmd]),
+            parse_columns("pid,sz,cmd")
+        );
+        assert_eq!(None, parse_columns("pid,garbage"));
+    }
+
+    #[test]
+    fn ts_count_dir_entries() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(Some(0), count_dir_entries(dir.path()));
+
+        for fd in 0..3 {
+            fs::write(dir.path().join(fd.to_string()), b"").unwrap();
+        }
+        assert_eq!(Some(3), count_dir_entries(dir.path()));
+
+        // unreadable directories
+        assert_eq!(None, count_dir_entries(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn ts_format_stat_foreground() {
+        assert!(is_foreground(200, 200));
+        assert!(!is_foreground(200, 300));
+        assert!(!is_foreground(-1, -1));
+
+        // a shell running a job in the foreground
+        let shell = process_info(200, 1, &["bash"]);
+        let mut foreground = process_info(300, 200, &["vim"]);
+        let mut background = process_info(400, 200, &["make"]);
+        foreground.tpgid = 300;
+        background.tpgid = 300;
+        assert_eq!("S", column_value(Column::Stat, &shell, false));
+        assert_eq!("S+", column_value(Column::Stat, &foreground, false));
+        assert_eq!("S", column_value(Column::Stat, &background, false));
+
+        // the processes of a pipeline share the process group
+        let mut pipeline = process_info(301, 200, &["less"]);
+        pipeline.pgrp = 300;
+        pipeline.tpgid = 300;
+        pipeline.state = 'R';
+        assert_eq!("R+", format_stat(&pipeline));
+
+        assert_eq!(
+            Some(vec![Column::Pid, Column::Stat]),
+            parse_columns("pid,stat")
+        );
+    }
+
+    #[test]
+    fn ts_policy_name() {
+        let names = [
+            "SCHED_OTHER",
+            "SCHED_FIFO",
+            "SCHED_RR",
+            "SCHED_BATCH",
+            "4",
+            "SCHED_IDLE",
+            "SCHED_DEADLINE",
+        ];
+        for (policy, name) in names.iter().enumerate() {
+            assert_eq!(*name, policy_name(Some(policy as u32)));
+        }
+        assert_eq!("42", policy_name(Some(42)));
+        assert_eq!("-", policy_name(None));
+
+        let mut info = process_info(200, 1, &["bash"]);
+        assert_eq!("SCHED_OTHER", column_value(Column::Policy, &info, false));
+        info.policy = Some(1);
+        assert_eq!("SCHED_FIFO", column_value(Column::Policy, &info, false));
+        assert_eq!(
+            Some(vec![Column::Pid, Column::Policy]),
+            parse_columns("pid,policy")
+        );
+    }
+
+    #[test]
+    fn ts_format_details() {
+        let cmd_options = read_arguments(vec!["ps", "--details", "200"]);
+        assert_eq!(Some(200), cmd_options.details);
+        let mut info = process_info(200, 1, &["bash", "-l"]);
+        info.fds = Some(4);
+        let lines = format_details(&info, &cmd_options);
+
+        assert_eq!(14, lines.len());
+        assert_eq!("PID:    200", lines[0]);
+        assert_eq!("PPID:   1", lines[1]);
+        assert_eq!("USER:   root", lines[2]);
+        assert_eq!("STAT:   S", lines[4]);
+        assert_eq!("POLICY: SCHED_OTHER", lines[5]);
+        assert_eq!("RSS:    1024", lines[9]);
+        assert_eq!("FDS:    4", lines[11]);
+        assert_eq!("CMD:    cmd", lines[12]);
+        assert_eq!("ARGS:   bash -l", lines[13]);
+
+        // the memory sizes follow -h, the variable of --show-env is included
+        let cmd_options =
+            read_arguments(vec!["ps", "-h", "--show-env", "LANG", "--details", "200"]);
+        let lines = format_details(&info, &cmd_options);
+        assert_eq!("RSS:    1.0M", lines[9]);
+        assert_eq!("LANG:   -", lines[12]);
+
+        let processes = process_list();
+        assert_eq!(
+            Err(ErrCode::ErrorProcessNotFound),
+            print_details(&processes, &cmd_options, 42)
+        );
+    }
+
+    #[test]
+    fn ts_parse_environ() {
+        let environ = parse_environ(b"LANG=C.UTF-8\0PATH=/usr/bin:/bin\0EMPTY=\0A=b=c\0INVALID\0");
+        assert_eq!(Some("C.UTF-8"), environ.get("LANG").map(String::as_str));
+        assert_eq!(
+            Some("/usr/bin:/bin"),
+            environ.get("PATH").map(String::as_str)
+        );
+        assert_eq!(Some(""), environ.get("EMPTY").map(String::as_str));
+        assert_eq!(Some("b=c"), environ.get("A").map(String::as_str));
+        assert_eq!(4, environ.len());
+        assert!(parse_environ(b"").is_empty());
+    }
+
+    #[test]
+    fn ts_show_env() {
+        let cmd_options = read_arguments(vec!["ps", "--show-env", "LANG"]);
+        assert_eq!(Some(String::from("LANG")), cmd_options.show_env);
+        assert_eq!(
+            vec![
+                Column::Pid,
+                Column::Tty,
+                Column::Time,
+                Column::Env,
+                Column::Cmd
+            ],
+            cmd_options.columns
+        );
+        let cmd_options = read_arguments(vec!["ps", "-o", "cmd,pid", "--show-env", "LANG"]);
+        assert_eq!(
+            vec![Column::Cmd, Column::Pid, Column::Env],
+            cmd_options.columns
+        );
+
+        let mut info = process_info(200, 1, &["bash"]);
+        assert_eq!("-", column_value(Column::Env, &info, false));
+        info.env = Some(String::from("C.UTF-8"));
+        assert_eq!("C.UTF-8", column_value(Column::Env, &info, false));
+    }
+
+    #[test]
+    fn ts_column_value_fds() {
+        let mut info = process_info(200, 1, &["bash"]);
+        assert_eq!("-", column_value(Column::Fds, &info, false));
+
+        info.fds = Some(4);
+        assert_eq!("4", column_value(Column::Fds, &info, false));
+    }
+
+    #[test]
+    fn ts_shows_fds() {
+        assert!(!read_arguments(vec!["ps"]).shows_fds());
+        assert!(read_arguments(vec!["ps", "-o", "pid,fds,cmd"]).shows_fds());
+        assert!(read_arguments(vec!["ps", "--details", "1"]).shows_fds());
+    }
+
+    #[test]
+    fn ts_cpu_percent() {
+        // 100 ticks at 100 ticks per second over 4 seconds
+        assert_eq!(25.0, cpu_percent(100, 100, 4.0));
+        // a large uptime keeps the precision of the seconds since the start
+        let uptime = 1_700_000_004.0_f64;
+        assert_eq!(25.0, cpu_percent(100, 100, uptime - 1_700_000_000.0));
+        assert_eq!(0.0, cpu_percent(100, 100, 0.0));
+    }
+
+    #[test]
+    fn ts_cpu_percent_delta() {
+        // 50 ticks at 100 ticks per second over a 2 seconds interval
+        assert_eq!(25.0, cpu_percent_delta(1000, 1050, 100, 2.0));
+        // a process using two CPUs
+        assert_eq!(200.0, cpu_percent_delta(1000, 1400, 100, 2.0));
+        // an idle process
+        assert_eq!(0.0, cpu_percent_delta(1000, 1000, 100, 2.0));
+        // a reused pid with fewer ticks than the previous sample
+        assert_eq!(0.0, cpu_percent_delta(1000, 10, 100, 2.0));
+    }
+
+    #[test]
+    fn ts_apply_cpu_deltas() {
+        let mut processes = vec![
+            process_info(1, 0, &["/sbin/init"]),
+            process_info(200, 1, &["bash"]),
+        ];
+        processes[0].ticks = 5000;
+        processes[0].cpu = 1.5;
+        processes[1].ticks = 300;
+        processes[1].cpu = 40.0;
+
+        // the shell started after the previous sample
+        let previous: HashMap<i32, u64> = vec![(1, 4900)].into_iter().collect();
+        apply_cpu_deltas(&mut processes, &previous, 100, 1.0);
+        assert_eq!(100.0, processes[0].cpu);
+        assert_eq!(40.0, processes[1].cpu);
+        assert_eq!("100.0", column_value(Column::Cpu, &processes[0], false));
+    }
+
+    #[test]
+    fn ts_humanize_kb() {
+        assert_eq!("0K", humanize_kb(0));
+        assert_eq!("1023K", humanize_kb(1023));
+        assert_eq!("1.0M", humanize_kb(1024));
+        assert_eq!("1.5M", humanize_kb(1536));
+        assert_eq!("512.0M", humanize_kb(512 * 1024));
+        assert_eq!("1023.9M", humanize_kb(1024 * 1024 - 103));
+        assert_eq!("1.0G", humanize_kb(1024 * 1024 - 1));
+        assert_eq!("1.0G", humanize_kb(1024 * 1024));
+        assert_eq!("1.2G", humanize_kb(1258292));
+        assert_eq!("2.0T", humanize_kb(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn ts_column_value_memory() {
+        let info = process_info(200, 1, &["bash"]);
+
+        // raw values: pages for SZ, KB for RSS and VSZ
+        assert_eq!("256", column_value(Column::Sz, &info, false));
+        assert_eq!("1024", column_value(Column::Rss, &info, false));
+        assert_eq!("1536", column_value(Column::Vsz, &info, false));
+
+        // human readable
+        assert_eq!("1.0M", column_value(Column::Sz, &info, true));
+        assert_eq!("1.0M", column_value(Column::Rss, &info, true));
+        assert_eq!("1.5M", column_value(Column::Vsz, &info, true));
+
+        // the other columns are not affected
+        assert_eq!("200", column_value(Column::Pid, &info, true));
+    }
+
+    #[test]
+    fn ts_pid_width() {
+        let columns = [Column::Pid, Column::Ppid, Column::Cmd];
+        let mut processes = process_list();
+        assert_eq!(5, pid_width(&processes));
+        assert_eq!(5, pid_width(&[]));
+        let values = vec![String::from("200"), String::from("1"), String::from("bash")];
+        assert_eq!("  200     1 bash", format_row(&columns, &values, 5));
+
+        // the columns widen for a seven digit pid
+        processes.push(process_info(4194303, 1, &["sleep"]));
+        let width = pid_width(&processes);
+        assert_eq!(7, width);
+        let headers = vec![
+            String::from("PID"),
+            String::from("PPID"),
+            String::from("CMD"),
+        ];
+        assert_eq!("    PID    PPID CMD", format_row(&columns, &headers, width));
+        assert_eq!("    200       1 bash", format_row(&columns, &values, width));
+        let values = vec![
+            String::from("4194303"),
+            String::from("1"),
+            String::from("sleep"),
+        ];
+        assert_eq!(
+            "4194303       1 sleep",
+            format_row(&columns, &values, width)
+        );
+
+        // a parent with a long pid widens the columns too
+        processes.push(process_info(5, 12345678, &["sh"]));
+        assert_eq!(8, pid_width(&processes));
+    }
+
+    #[test]
+    fn ts_summarize() {
+        let mut processes = process_list();
+        processes[2].time = 1.5;
+        processes[3].time = 0.25;
+        processes[3].rss = 2048;
+
+        let summary = summarize(&processes);
+        assert_eq!(
+            Summary {
+                processes: 5,
+                rss: 4 * 1024 + 2048,
+                time: 1.75,
+            },
+            summary
+        );
+        assert_eq!(
+            "TOTAL: 5 processes, RSS 6144, TIME 1.75",
+            format_summary(&summary, false)
+        );
+        assert_eq!(
+            "TOTAL: 5 processes, RSS 6.0M, TIME 1.75",
+            format_summary(&summary, true)
+        );
+
+        // no processes
+        assert_eq!(0, summarize(&[]).processes);
+
+        assert!(read_arguments(vec!["ps", "--summary"]).summary);
+    }
+
+    #[test]
+    fn ts_is_kernel_thread() {
+        assert!(is_kernel_thread(&process_info(2, 0, &[])));
+        assert!(is_kernel_thread(&process_info(10, 2, &[])));
+        assert!(!is_kernel_thread(&process_info(1, 0, &["/sbin/init"])));
+        assert!(!is_kernel_thread(&process_info(200, 1, &["bash"])));
+    }
+
+    #[test]
+    fn ts_select_processes() {
+        let pids = |v: Vec<ProcessInfo>| v.iter().map(|p| p.pid).collect::<Vec<i32>>();
+
+        // by default ps does not list itself
+        let cmd_options = CommandLineOptions::new();
+        let selected = select_processes(process_list(), &cmd_options, 300);
+        assert_eq!(vec![2, 10, 1, 200], pids(selected));
+
+        // -e lists ps too
+        let mut cmd_options = CommandLineOptions::new();
+        cmd_options.all_processes = true;
+        let selected = select_processes(process_list(), &cmd_options, 300);
+        assert_eq!(vec![2, 10, 1, 200, 300], pids(selected));
+
+        // --no-kthreads only keeps the userspace processes
+        cmd_options.no_kthreads = true;
+        let selected = select_processes(process_list(), &cmd_options, 300);
+        assert_eq!(vec![1, 200, 300], pids(selected));
+    }
+} // mod ps_ts
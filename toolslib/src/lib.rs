@@ -1,6 +1,8 @@
 /// toolslib
 ///
 /// Library of common functions to the Unixtools
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::Path;
 
 /// Exit codes, note that Process::exit requires i32 as argument
@@ -16,8 +18,22 @@ pub enum ErrCode {
     ErrorArgumentParsing = 4,
     /// Error missing input argument
     ErrorMissingInputArgument = 5,
+    /// The reader of the standard output closed the pipe, not reported as a failure
+    ErrorBrokenPipe = 6,
+    /// The input is a directory
+    ErrorInputIsADirectory = 7,
     /// Error creating regular expression
     InvalidRegularExpression = 30,
+    /// Error reading the process information from /proc
+    ErrorReadingProcFs = 40,
+    /// Error the process does not exist
+    ErrorProcessNotFound = 41,
+    /// Error the dump does not match the input file
+    ErrorVerificationFailed = 50,
+    /// Error the dump file format is not valid
+    ErrorInvalidDumpFormat = 51,
+    /// Error the signature is not found in the input
+    ErrorSignatureNotFound = 52,
 }
 
 /// Gets a vector of strings as an input argument and returns an array of valid  Paths.
@@ -32,8 +48,7 @@ pub enum ErrCode {
 /// arguments. If `ignore_errors` is set to false returns error if any string
 /// corresponds to an invalid path.
 pub fn get_file_paths(inputs: &Vec<String>, ignore_errors: bool) -> Result<Vec<&Path>, ErrCode> {
-    let mut file_paths = Vec::new();
-    file_paths.reserve(inputs.len() + 1);
+    let mut file_paths = Vec::with_capacity(inputs.len() + 1);
 
     for file_name in inputs {
         if "-" == file_name {
@@ -52,3 +67,122 @@ pub fn get_file_paths(inputs: &Vec<String>, ignore_errors: bool) -> Result<Vec<&
     }
     Ok(file_paths)
 }
+
+/// Reads the whole contents of an input.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to read, `-` reads the standard input
+///
+/// Returns `ErrCode::ErrorCannotOpenFileForReading` if the input can not be
+/// opened or read.
+pub fn read_all(path: &str) -> Result<Vec<u8>, ErrCode> {
+    read_all_with_stdin(path, io::stdin())
+}
+
+/// Reads the whole contents of an input like `read_all`, `stdin` is read
+/// when the path is `-`.
+pub fn read_all_with_stdin<R: Read>(path: &str, stdin: R) -> Result<Vec<u8>, ErrCode> {
+    let read = |mut reader: Box<dyn Read>| -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    };
+
+    let reader: io::Result<Box<dyn Read>> = if path == "-" {
+        Ok(Box::new(stdin))
+    } else {
+        File::open(path).map(|file| Box::new(file) as Box<dyn Read>)
+    };
+    match reader.and_then(read) {
+        Ok(buf) => Ok(buf),
+        Err(err) => {
+            eprintln!("ERROR: can not read `{}`: {}", path, err);
+            Err(ErrCode::ErrorCannotOpenFileForReading)
+        }
+    }
+}
+
+/// Splits a byte buffer into lines, keeping the line terminators.
+///
+/// # Arguments
+///
+/// * `buf` - The bytes to split
+///
+/// Returns an iterator of `(content, terminator)` pairs where the terminator is
+/// `\n`, `\r\n`, or empty for a last line that does not end with a newline.
+/// Concatenating all the pairs reconstructs the original buffer.
+pub fn split_lines_with_terminators(buf: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = buf;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let (line, tail) = match rest.iter().position(|&b| b == b'\n') {
+            Some(i) => rest.split_at(i + 1),
+            None => rest.split_at(rest.len()),
+        };
+        rest = tail;
+
+        let content_len = if line.ends_with(b"\r\n") {
+            line.len() - 2
+        } else if line.ends_with(b"\n") {
+            line.len() - 1
+        } else {
+            line.len()
+        };
+        Some(line.split_at(content_len))
+    })
+}
+
+/// Parses a size argument into a number of bytes.
+///
+/// # Arguments
+///
+/// * `s` - The size as a decimal number, or hexadecimal with the `0x` prefix,
+///   optionally followed by a multiplicative suffix.
+///
+/// The suffixes `K`, `M`, `G` and `T`, optionally followed by `iB`, are powers
+/// of 1024; the suffixes `KB`, `MB`, `GB` and `TB` are powers of 1000. The
+/// suffixes are case insensitive. Returns `ErrCode::ErrorArgumentParsing` if
+/// the string is not a valid size or the size overflows.
+pub fn parse_size(s: &str) -> Result<u64, ErrCode> {
+    let invalid = || {
+        eprintln!("ERROR: invalid size: `{}`", s);
+        ErrCode::ErrorArgumentParsing
+    };
+
+    let lower = s.trim().to_ascii_lowercase();
+    let (number, radix, suffix) = if let Some(hex) = lower.strip_prefix("0x") {
+        let end = hex
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(hex.len());
+        (&hex[..end], 16, &hex[end..])
+    } else {
+        let end = lower
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(lower.len());
+        (&lower[..end], 10, &lower[end..])
+    };
+
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(invalid()),
+    };
+
+    if number.is_empty() {
+        return Err(invalid());
+    }
+    u64::from_str_radix(number, radix)
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
@@ -43,4 +43,97 @@ mod toolslib {
             Err(_) => {}
         }
     }
+
+    #[test]
+    fn ts_split_lines_with_terminators_lf() {
+        let lines: Vec<_> = split_lines_with_terminators(b"lorem\nipsum\n\ndolor\n").collect();
+        assert_eq!(4, lines.len());
+        assert_eq!((&b"lorem"[..], &b"\n"[..]), lines[0]);
+        assert_eq!((&b"ipsum"[..], &b"\n"[..]), lines[1]);
+        assert_eq!((&b""[..], &b"\n"[..]), lines[2]);
+        assert_eq!((&b"dolor"[..], &b"\n"[..]), lines[3]);
+    }
+
+    #[test]
+    fn ts_split_lines_with_terminators_crlf() {
+        let lines: Vec<_> = split_lines_with_terminators(b"lorem\r\nipsum\ndolor\r\n").collect();
+        assert_eq!(3, lines.len());
+        assert_eq!((&b"lorem"[..], &b"\r\n"[..]), lines[0]);
+        assert_eq!((&b"ipsum"[..], &b"\n"[..]), lines[1]);
+        assert_eq!((&b"dolor"[..], &b"\r\n"[..]), lines[2]);
+    }
+
+    #[test]
+    fn ts_split_lines_with_terminators_no_trailing_newline() {
+        let buf = b"lorem\nipsum\xff";
+        let lines: Vec<_> = split_lines_with_terminators(buf).collect();
+        assert_eq!(2, lines.len());
+        assert_eq!((&b"lorem"[..], &b"\n"[..]), lines[0]);
+        assert_eq!((&b"ipsum\xff"[..], &b""[..]), lines[1]);
+
+        // the original bytes can be reconstructed
+        let mut rebuilt: Vec<u8> = Vec::new();
+        for (content, terminator) in lines {
+            rebuilt.extend_from_slice(content);
+            rebuilt.extend_from_slice(terminator);
+        }
+        assert_eq!(buf.to_vec(), rebuilt);
+
+        // empty buffers have no lines
+        assert_eq!(0, split_lines_with_terminators(b"").count());
+    }
+
+    #[test]
+    fn ts_parse_size() {
+        assert_eq!(Ok(1024), parse_size("1024"));
+        assert_eq!(Ok(1024), parse_size("1k"));
+        assert_eq!(Ok(1024), parse_size("1KiB"));
+        assert_eq!(Ok(1000), parse_size("1KB"));
+        assert_eq!(Ok(2 * 1024 * 1024), parse_size("2M"));
+        assert_eq!(Ok(3 * 1024 * 1024 * 1024), parse_size("3g"));
+        assert_eq!(Ok(16), parse_size("0x10"));
+        assert_eq!(Ok(16 * 1024), parse_size("0x10k"));
+    }
+
+    #[test]
+    fn ts_parse_size_invalid() {
+        assert_eq!(Err(ErrCode::ErrorArgumentParsing), parse_size("lorem"));
+        assert_eq!(Err(ErrCode::ErrorArgumentParsing), parse_size(""));
+        assert_eq!(Err(ErrCode::ErrorArgumentParsing), parse_size("0x"));
+        assert_eq!(Err(ErrCode::ErrorArgumentParsing), parse_size("10q"));
+        assert_eq!(Err(ErrCode::ErrorArgumentParsing), parse_size("-1"));
+
+        // overflow
+        assert_eq!(
+            Err(ErrCode::ErrorArgumentParsing),
+            parse_size("18446744073709551616")
+        );
+        assert_eq!(
+            Err(ErrCode::ErrorArgumentParsing),
+            parse_size("0xffffffffffffffffk")
+        );
+    }
+
+    #[test]
+    fn ts_read_all() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("f1");
+        std::fs::write(&path, b"lorem\n\x00\xff").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(Ok(b"lorem\n\x00\xff".to_vec()), read_all(path));
+
+        // the standard input
+        let stdin: &[u8] = b"ipsum";
+        assert_eq!(Ok(b"ipsum".to_vec()), read_all_with_stdin("-", stdin));
+        assert_eq!(
+            Ok(b"lorem\n\x00\xff".to_vec()),
+            read_all_with_stdin(path, stdin)
+        );
+
+        let missing = dir.path().join("missing");
+        assert_eq!(
+            Err(ErrCode::ErrorCannotOpenFileForReading),
+            read_all(missing.to_str().unwrap())
+        );
+    }
 }